@@ -0,0 +1,134 @@
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use anyhow::Result;
+use log::{info, warn};
+use solana_sdk::pubkey::Pubkey;
+use tokio::time::{sleep, Duration};
+use crate::config::{BackfillConfig, Config};
+use crate::mints::MintResolver;
+use crate::parser::{decode_pumpfun_txn, decode_raydium_txn, transaction_account_keys};
+use crate::positions::PositionBook;
+use crate::rpc::RpcClient;
+use crate::trade::{Side, TradeEvent};
+use crate::utils::{pumpfun_parser::PUMPFUN_PROGRAM_ID, raydium_parser::RAYDIUM_AMM_V4_PROGRAM_ID};
+
+const SIGNATURES_PAGE_SIZE: usize = 1000;
+
+/// Rebuilds the open positions of `wallets` from their recent history, on startup and
+/// when targets are added. Live trades of a target are held until its backfill is
+/// over, then applied on top of it.
+pub fn start_backfill(config: &Config, wallets: Vec<String>, positions: Arc<Mutex<PositionBook>>, mints: Arc<MintResolver>) {
+    if !config.backfill.enabled {
+        return;
+    }
+    for wallet in &wallets {
+        lock(&positions).start_backfill(wallet);
+    }
+    let rpc = RpcClient::new(&config.rpc.endpoint);
+    let backfill = config.backfill.clone();
+    tokio::spawn(async move {
+        for wallet in &wallets {
+            match backfill_wallet(&rpc, &backfill, wallet, &positions, &mints).await {
+                Ok(trades) => log_wallet_summary(wallet, trades, &positions),
+                Err(e) => warn!("Backfill of {} failed: {:#}", wallet, e),
+            }
+            lock(&positions).finish_backfill(wallet);
+        }
+    });
+}
+
+fn lock(positions: &Mutex<PositionBook>) -> MutexGuard<'_, PositionBook> {
+    match positions.lock() {
+        Ok(positions) => positions,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Runs the last `signatures_per_wallet` transactions of `wallet` through the same
+/// flatten + decode pipeline as the live feed, oldest first, and applies the
+/// wallet's trades to its positions, net of transfer fees like live trades. Returns
+/// the number of trades found.
+pub async fn backfill_wallet(
+    rpc: &RpcClient,
+    config: &BackfillConfig,
+    wallet: &str,
+    positions: &Mutex<PositionBook>,
+    mints: &MintResolver,
+) -> Result<usize> {
+    let mut signatures = vec![];
+    let mut before: Option<String> = None;
+    while signatures.len() < config.signatures_per_wallet {
+        let limit = SIGNATURES_PAGE_SIZE.min(config.signatures_per_wallet - signatures.len());
+        let page = rpc.get_signatures_for_address(wallet, before.as_deref(), limit).await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        before = Some(last.signature.clone());
+        let page_len = page.len();
+        signatures.extend(page.into_iter().filter(|info| info.err.is_none()));
+        if page_len < limit {
+            break;
+        }
+    }
+    info!("Backfilling {} from {} transactions", wallet, signatures.len());
+
+    let mut found = 0;
+    for info in signatures.iter().rev() {
+        sleep(Duration::from_millis(config.request_delay_ms)).await;
+        let trades = match fetch_trades(rpc, &info.signature).await {
+            Ok(trades) => trades,
+            Err(e) => {
+                warn!("Backfill skipped {}: {:#}", info.signature, e);
+                continue;
+            }
+        };
+        let trades: Vec<TradeEvent> = trades.into_iter().filter(|trade| trade.wallet == wallet).collect();
+        for trade in trades.iter().filter(|trade| trade.side == Side::Buy) {
+            if let Err(e) = resolve_mint(mints, &trade.mint).await {
+                warn!("Backfill could not resolve mint {}, its transfer fee is ignored: {:#}", trade.mint, e);
+            }
+        }
+        let mut positions = lock(positions);
+        for trade in &trades {
+            if positions.apply(&mints.received(trade)) {
+                found += 1;
+            }
+        }
+    }
+    Ok(found)
+}
+
+async fn fetch_trades(rpc: &RpcClient, signature: &str) -> Result<Vec<TradeEvent>> {
+    let Some((slot, tx)) = rpc.get_transaction(signature).await? else {
+        return Ok(vec![]);
+    };
+    let account_keys = transaction_account_keys(&tx)?;
+    let mut trades = vec![];
+    if account_keys.contains(&RAYDIUM_AMM_V4_PROGRAM_ID) {
+        trades.extend(decode_raydium_txn(&tx, slot)?);
+    }
+    if account_keys.contains(&PUMPFUN_PROGRAM_ID) {
+        trades.extend(decode_pumpfun_txn(&tx, slot, false)?);
+    }
+    Ok(trades)
+}
+
+async fn resolve_mint(mints: &MintResolver, mint: &str) -> Result<()> {
+    mints.resolve(&Pubkey::from_str(mint)?).await?;
+    Ok(())
+}
+
+fn log_wallet_summary(wallet: &str, trades: usize, positions: &Mutex<PositionBook>) {
+    let positions = lock(positions);
+    let score = positions.score(wallet);
+    info!(
+        "Backfilled {}: {} trades, {} closed positions ({} winning), realized pnl {} lamports",
+        wallet, trades, score.closed_positions, score.winning_positions, score.realized_pnl
+    );
+    for (mint, position) in positions.open_positions(wallet) {
+        info!(
+            "  open {:?} position in {} ({}): {} tokens for {} lamports since slot {}",
+            position.dex, mint, position.pool, position.token_amount, position.sol_spent, position.opened_slot
+        );
+    }
+}
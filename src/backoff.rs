@@ -0,0 +1,80 @@
+use rand::Rng;
+use tokio::time::{Duration, Instant};
+use crate::config::{GrpcEndpoint, ReconnectConfig};
+
+/// Reconnect policy for a monitor loop: exponential backoff with jitter between
+/// attempts, failover through the configured endpoints after repeated failures, and
+/// periodic probing of the primary endpoint while running on a fallback.
+pub struct Reconnector {
+    endpoints: Vec<GrpcEndpoint>,
+    config: ReconnectConfig,
+    current: usize,
+    attempt: u32,
+    failures: u32,
+    last_failback_probe: Instant,
+}
+
+impl Reconnector {
+    pub fn new(endpoints: Vec<GrpcEndpoint>, config: ReconnectConfig) -> Self {
+        Self {
+            endpoints,
+            config,
+            current: 0,
+            attempt: 0,
+            failures: 0,
+            last_failback_probe: Instant::now(),
+        }
+    }
+
+    pub fn endpoint(&self) -> &GrpcEndpoint {
+        &self.endpoints[self.current]
+    }
+
+    pub fn primary(&self) -> &GrpcEndpoint {
+        &self.endpoints[0]
+    }
+
+    pub fn on_primary(&self) -> bool {
+        self.current == 0
+    }
+
+    /// Called once the stream delivers data, the endpoint is considered healthy again.
+    pub fn on_connected(&mut self) {
+        self.attempt = 0;
+        self.failures = 0;
+    }
+
+    /// Registers a failed or dropped connection and returns how long to wait before
+    /// the next attempt. Fails over to the next endpoint after `failover_after`
+    /// consecutive failures.
+    pub fn on_failure(&mut self) -> Duration {
+        self.failures += 1;
+        if self.failures >= self.config.failover_after.max(1) && self.endpoints.len() > 1 {
+            self.current = (self.current + 1) % self.endpoints.len();
+            self.failures = 0;
+            self.last_failback_probe = Instant::now();
+        }
+
+        let base = self.config.initial_backoff_ms as f64 * self.config.multiplier.powi(self.attempt as i32);
+        let capped = base.min(self.config.max_backoff_ms as f64);
+        let jitter = self.config.jitter.clamp(0.0, 1.0);
+        let delay = capped * (1.0 - jitter * rand::thread_rng().gen::<f64>());
+        self.attempt = self.attempt.saturating_add(1);
+        Duration::from_millis(delay as u64)
+    }
+
+    /// Whether it is time to check if the primary endpoint is reachable again.
+    pub fn should_probe_primary(&mut self) -> bool {
+        if self.on_primary() || self.last_failback_probe.elapsed() < Duration::from_secs(self.config.failback_probe_secs) {
+            return false;
+        }
+        self.last_failback_probe = Instant::now();
+        true
+    }
+
+    pub fn fail_back(&mut self) {
+        self.current = 0;
+        self.attempt = 0;
+        self.failures = 0;
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use crate::config::LatencyConfig;
+
+/// When a simulated copy lands: once the clock passes `deadline`, or earlier when
+/// its pool has seen enough swaps after the copied trade.
+#[derive(Debug, Clone, Copy)]
+pub struct Landing {
+    deadline: u64,
+    swap_count: Option<u64>,
+}
+
+/// Delays copies by a number of slots or of intervening swaps on the same pool, so
+/// they are priced against the pool state they would actually hit instead of the
+/// state the target left behind.
+#[derive(Debug)]
+pub struct LatencyModel {
+    config: LatencyConfig,
+    swaps: HashMap<String, u64>,
+}
+
+impl LatencyModel {
+    pub fn new(config: &LatencyConfig) -> Self {
+        Self { config: config.clone(), swaps: HashMap::new() }
+    }
+
+    /// Counts a swap observed on `pool`.
+    pub fn observe(&mut self, pool: &str) {
+        *self.swaps.entry(pool.to_owned()).or_default() += 1;
+    }
+
+    pub fn schedule(&self, pool: &str, slot: u64) -> Landing {
+        match self.config {
+            LatencyConfig::Slots { slots } => Landing { deadline: slot + slots, swap_count: None },
+            LatencyConfig::Swaps { swaps, max_slots } => Landing {
+                deadline: slot + max_slots,
+                swap_count: Some(self.swap_count(pool) + swaps),
+            },
+        }
+    }
+
+    /// True if the copy lands before any trade of `slot`.
+    pub fn due_at_slot(&self, landing: &Landing, slot: u64) -> bool {
+        landing.deadline < slot
+    }
+
+    /// True if the copy lands right after the latest swap on `pool`.
+    pub fn due_after_swap(&self, landing: &Landing, pool: &str) -> bool {
+        landing.swap_count.is_some_and(|count| self.swap_count(pool) >= count)
+    }
+
+    fn swap_count(&self, pool: &str) -> u64 {
+        self.swaps.get(pool).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slot_latency_lands_before_the_first_later_slot() {
+        let model = LatencyModel::new(&LatencyConfig::Slots { slots: 2 });
+        let landing = model.schedule("pool", 10);
+        assert!(!model.due_at_slot(&landing, 12));
+        assert!(model.due_at_slot(&landing, 13));
+        assert!(!model.due_after_swap(&landing, "pool"));
+    }
+
+    #[test]
+    fn swap_latency_counts_swaps_on_the_same_pool() {
+        let mut model = LatencyModel::new(&LatencyConfig::Swaps { swaps: 2, max_slots: 5 });
+        // The copied trade itself
+        model.observe("pool");
+        let landing = model.schedule("pool", 10);
+
+        model.observe("pool");
+        model.observe("other");
+        assert!(!model.due_after_swap(&landing, "pool"));
+        model.observe("pool");
+        assert!(model.due_after_swap(&landing, "pool"));
+    }
+
+    #[test]
+    fn swap_latency_lands_after_max_slots_on_a_quiet_pool() {
+        let model = LatencyModel::new(&LatencyConfig::Swaps { swaps: 2, max_slots: 5 });
+        let landing = model.schedule("pool", 10);
+        assert!(!model.due_at_slot(&landing, 15));
+        assert!(model.due_at_slot(&landing, 16));
+    }
+}
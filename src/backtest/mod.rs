@@ -0,0 +1,67 @@
+mod latency;
+mod report;
+mod simulator;
+
+use std::collections::HashSet;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use yellowstone_grpc_proto::convert_from;
+use yellowstone_grpc_proto::prelude::subscribe_update::UpdateOneof;
+use crate::config::Config;
+use crate::parser::{decode_pumpfun_txn, decode_raydium_txn};
+use crate::recorder::{list_recordings, RecordingReader};
+use crate::trade::TradeEvent;
+use self::simulator::Simulator;
+
+/// Replays the recorded streams offline through the decoders, applies the copy
+/// strategy from `backtest` and prints the results per target wallet.
+pub fn run(config: &Config) -> Result<()> {
+    let trades = load_trades(config)?;
+    info!("Backtesting {} recorded trades", trades.len());
+
+    let mut simulator = Simulator::new(config);
+    for trade in &trades {
+        simulator.on_trade(trade);
+    }
+    let report = simulator.finish();
+    report.print();
+    Ok(())
+}
+
+/// Decodes every recorded transaction of both DEXes into trade events, ordered by
+/// slot. Copies of a transaction recorded from several feeds are kept once.
+fn load_trades(config: &Config) -> Result<Vec<TradeEvent>> {
+    let targets: HashSet<&String> = config.wallets.active_targets().map(|target| &target.wallet).collect();
+    let mut seen = HashSet::new();
+    let mut trades = vec![];
+
+    for (selector, prefix) in [(0, "raydium"), (1, "pumpfun")] {
+        for path in list_recordings(&config.backtest.dir, prefix)? {
+            for frame in RecordingReader::open(&path)? {
+                let frame = frame.with_context(|| format!("Corrupted recording {}", path.display()))?;
+                let Some(UpdateOneof::Transaction(tx)) = frame.update.and_then(|update| update.update_oneof) else {
+                    continue;
+                };
+                let Some(transaction) = tx.transaction else {
+                    continue;
+                };
+                if !seen.insert(transaction.signature.clone()) {
+                    continue;
+                }
+                let decoded = convert_from::create_tx_with_meta(transaction)
+                    .map_err(|error| anyhow::anyhow!(error))
+                    .and_then(|tx_with_meta| match selector {
+                        0 => decode_raydium_txn(&tx_with_meta, tx.slot),
+                        _ => decode_pumpfun_txn(&tx_with_meta, tx.slot, true),
+                    });
+                match decoded {
+                    Ok(decoded) => trades.extend(decoded.into_iter().filter(|trade| targets.is_empty() || targets.contains(&trade.wallet))),
+                    Err(e) => warn!("Backtest skipped a transaction at slot {}: {:#}", tx.slot, e),
+                }
+            }
+        }
+    }
+
+    trades.sort_by_key(|trade| trade.slot);
+    Ok(trades)
+}
@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+
+/// Results of the copy strategy against one target wallet.
+#[derive(Debug, Default, Clone)]
+pub struct WalletReport {
+    pub copies: u64,
+    pub skipped: u64,
+    pub closed_positions: u64,
+    pub winning_positions: u64,
+    pub realized_pnl: i64,
+    pub unrealized_pnl: i64,
+    pub open_positions: u64,
+    peak_pnl: i64,
+    pub max_drawdown: i64,
+    slippage_bps_total: f64,
+    slippage_samples: u64,
+}
+
+impl WalletReport {
+    pub fn record_close(&mut self, pnl: i64) {
+        self.closed_positions += 1;
+        if pnl > 0 {
+            self.winning_positions += 1;
+        }
+        self.realized_pnl += pnl;
+        self.peak_pnl = self.peak_pnl.max(self.realized_pnl);
+        self.max_drawdown = self.max_drawdown.max(self.peak_pnl - self.realized_pnl);
+    }
+
+    /// Slippage of our fill against the target's execution price, in basis points
+    /// (positive means we got a worse price).
+    pub fn record_slippage(&mut self, slippage_bps: f64) {
+        self.slippage_bps_total += slippage_bps;
+        self.slippage_samples += 1;
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        if self.closed_positions == 0 {
+            return 0.0;
+        }
+        self.winning_positions as f64 / self.closed_positions as f64
+    }
+
+    pub fn avg_slippage_bps(&self) -> f64 {
+        if self.slippage_samples == 0 {
+            return 0.0;
+        }
+        self.slippage_bps_total / self.slippage_samples as f64
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BacktestReport {
+    pub wallets: BTreeMap<String, WalletReport>,
+}
+
+impl BacktestReport {
+    pub fn wallet(&mut self, wallet: &str) -> &mut WalletReport {
+        self.wallets.entry(wallet.to_owned()).or_default()
+    }
+
+    pub fn print(&self) {
+        println!(
+            "{:<46} {:>7} {:>7} {:>7} {:>8} {:>12} {:>12} {:>12} {:>10}",
+            "wallet", "copies", "skipped", "closed", "hit rate", "pnl SOL", "open SOL", "drawdown", "slip bps"
+        );
+        for (wallet, report) in &self.wallets {
+            println!(
+                "{:<46} {:>7} {:>7} {:>7} {:>7.1}% {:>12.4} {:>12.4} {:>12.4} {:>10.1}",
+                wallet,
+                report.copies,
+                report.skipped,
+                report.closed_positions,
+                report.hit_rate() * 100.0,
+                report.realized_pnl as f64 / LAMPORTS_PER_SOL,
+                report.unrealized_pnl as f64 / LAMPORTS_PER_SOL,
+                report.max_drawdown as f64 / LAMPORTS_PER_SOL,
+                report.avg_slippage_bps(),
+            );
+        }
+    }
+}
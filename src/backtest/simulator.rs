@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use crate::config::{BacktestConfig, Config, FiltersConfig, SizingConfig, TargetProfile};
+use crate::positions::PositionBook;
+use crate::quote::pumpfun::BondingCurve;
+use crate::quote::raydium::{quote_exact_in, RaydiumFees};
+use crate::trade::{Dex, Side, TradeEvent};
+use super::latency::{Landing, LatencyModel};
+use super::report::BacktestReport;
+
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+
+#[derive(Debug, Clone, Copy)]
+struct PoolState {
+    sol_reserve: u64,
+    token_reserve: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum OrderKind {
+    /// Lamports to spend
+    Buy(u64),
+    /// Fraction of the position to sell
+    Sell(f64),
+}
+
+/// A copy waiting for its simulated landing.
+#[derive(Debug)]
+struct Order {
+    landing: Landing,
+    wallet: String,
+    mint: String,
+    pool: String,
+    dex: Dex,
+    kind: OrderKind,
+    /// Target's execution price in lamports per token, to measure our slippage
+    target_price: Option<f64>,
+}
+
+/// Our simulated holding of a mint, bought by copying one target.
+#[derive(Debug)]
+struct SimPosition {
+    dex: Dex,
+    pool: String,
+    tokens: u64,
+    cost: u64,
+    proceeds: u64,
+    opened_slot: u64,
+    exiting: bool,
+}
+
+/// Walks the target trades in slot order and simulates the copy strategy with
+/// constant-product fills against the pool state at which each copy lands.
+pub struct Simulator {
+    config: BacktestConfig,
+    /// Thresholds shared with the live copy filter
+    filters: FiltersConfig,
+    pumpfun_fee_bps: u64,
+    min_entry: f64,
+    max_entry: f64,
+    /// Wallets without a profile are copied with the backtest defaults
+    profiles: HashMap<String, TargetProfile>,
+    targets: PositionBook,
+    pools: HashMap<String, PoolState>,
+    latency: LatencyModel,
+    orders: Vec<Order>,
+    positions: HashMap<(String, String), SimPosition>,
+    report: BacktestReport,
+    slot: u64,
+}
+
+impl Simulator {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.backtest.clone(),
+            filters: config.filters.clone(),
+            pumpfun_fee_bps: config.dex.pumpfun.fee_basis_points,
+            min_entry: config.trade_settings.min_entry,
+            max_entry: config.trade_settings.max_entry,
+            profiles: config.wallets.active_targets().map(|target| (target.wallet.clone(), target.clone())).collect(),
+            targets: PositionBook::default(),
+            pools: HashMap::new(),
+            latency: LatencyModel::new(&config.backtest.latency),
+            orders: vec![],
+            positions: HashMap::new(),
+            report: BacktestReport::default(),
+            slot: 0,
+        }
+    }
+
+    pub fn on_trade(&mut self, trade: &TradeEvent) {
+        // Orders due before this slot land on the pool state left by the previous slots
+        if trade.slot > self.slot {
+            self.land(|latency, order| latency.due_at_slot(&order.landing, trade.slot));
+            self.slot = trade.slot;
+        }
+
+        let held = self.targets.position(&trade.wallet, &trade.mint).map(|position| position.token_amount).unwrap_or(0);
+        if !self.targets.apply(trade) {
+            return;
+        }
+        self.pools.insert(trade.pool.clone(), PoolState { sol_reserve: trade.sol_reserve, token_reserve: trade.token_reserve });
+        self.latency.observe(&trade.pool);
+        self.land(|latency, order| order.pool == trade.pool && latency.due_after_swap(&order.landing, &trade.pool));
+
+        let profile = self.profiles.get(&trade.wallet);
+        let copied = profile.is_none_or(|profile| profile.dexes.contains(&trade.dex) && profile.sides.allows(trade.side));
+        let follow_target_sells = profile.and_then(|profile| profile.exits.as_ref())
+            .unwrap_or(&self.config.exits)
+            .follow_target_sells;
+        match trade.side {
+            Side::Buy if copied => self.on_target_buy(trade),
+            Side::Buy => self.report.wallet(&trade.wallet).skipped += 1,
+            Side::Sell => {
+                let key = (trade.wallet.clone(), trade.mint.clone());
+                if copied && follow_target_sells && held > 0 && self.positions.contains_key(&key) {
+                    let fraction = (trade.token_amount as f64 / held as f64).min(1.0);
+                    self.place(trade, OrderKind::Sell(fraction));
+                }
+            }
+        }
+        self.check_exits();
+    }
+
+    /// Lands every pending order and marks the remaining positions to their last pool state.
+    pub fn finish(mut self) -> BacktestReport {
+        self.land(|_, _| true);
+        for ((wallet, _), position) in &self.positions {
+            let value = self.pools.get(&position.pool).map(|pool| quote_sell(position.dex, pool, position.tokens, self.pumpfun_fee_bps)).unwrap_or(0);
+            let report = self.report.wallet(wallet);
+            report.open_positions += 1;
+            report.unrealized_pnl += (position.proceeds + value) as i64 - position.cost as i64;
+        }
+        self.report
+    }
+
+    fn on_target_buy(&mut self, trade: &TradeEvent) {
+        let sol_amount = trade.sol_amount as f64 / LAMPORTS_PER_SOL;
+        if sol_amount < self.filters.min_target_sol || trade.price_impact > self.filters.max_price_impact {
+            self.report.wallet(&trade.wallet).skipped += 1;
+            return;
+        }
+        let profile = self.profiles.get(&trade.wallet);
+        let size = match profile.and_then(|profile| profile.sizing.as_ref()).unwrap_or(&self.config.sizing) {
+            SizingConfig::Fixed { amount } => *amount,
+            SizingConfig::Ratio { amount } => sol_amount * amount,
+        };
+        let mut lamports = (size.clamp(self.min_entry, self.max_entry) * LAMPORTS_PER_SOL) as u64;
+        if let Some(max_position) = profile.and_then(|profile| profile.max_position) {
+            let key = (trade.wallet.clone(), trade.mint.clone());
+            let invested = self.positions.get(&key).map(|position| position.cost).unwrap_or(0);
+            lamports = lamports.min(((max_position * LAMPORTS_PER_SOL) as u64).saturating_sub(invested));
+        }
+        if lamports == 0 {
+            self.report.wallet(&trade.wallet).skipped += 1;
+            return;
+        }
+        self.place(trade, OrderKind::Buy(lamports));
+    }
+
+    fn place(&mut self, trade: &TradeEvent, kind: OrderKind) {
+        let target_price = (trade.token_amount > 0).then(|| trade.sol_amount as f64 / trade.token_amount as f64);
+        self.orders.push(Order {
+            landing: self.latency.schedule(&trade.pool, trade.slot),
+            wallet: trade.wallet.clone(),
+            mint: trade.mint.clone(),
+            pool: trade.pool.clone(),
+            dex: trade.dex,
+            kind,
+            target_price,
+        });
+    }
+
+    fn land(&mut self, due: impl Fn(&LatencyModel, &Order) -> bool) {
+        let (due, pending) = std::mem::take(&mut self.orders).into_iter().partition(|order| due(&self.latency, order));
+        self.orders = pending;
+        for order in due {
+            self.execute(order);
+        }
+    }
+
+    fn execute(&mut self, order: Order) {
+        let Some(pool) = self.pools.get(&order.pool).copied() else {
+            return;
+        };
+        let key = (order.wallet.clone(), order.mint.clone());
+        match order.kind {
+            OrderKind::Buy(lamports) => {
+                let tokens = quote_buy(order.dex, &pool, lamports, self.pumpfun_fee_bps);
+                if tokens == 0 {
+                    return;
+                }
+                let report = self.report.wallet(&order.wallet);
+                report.copies += 1;
+                if let Some(target_price) = order.target_price {
+                    report.record_slippage((lamports as f64 / tokens as f64 / target_price - 1.0) * 10_000.0);
+                }
+                let position = self.positions.entry(key).or_insert_with(|| SimPosition {
+                    dex: order.dex,
+                    pool: order.pool.clone(),
+                    tokens: 0,
+                    cost: 0,
+                    proceeds: 0,
+                    opened_slot: self.slot,
+                    exiting: false,
+                });
+                position.tokens += tokens;
+                position.cost += lamports;
+            }
+            OrderKind::Sell(fraction) => {
+                let Some(position) = self.positions.get_mut(&key) else {
+                    return;
+                };
+                let tokens = if fraction >= 1.0 { position.tokens } else { (position.tokens as f64 * fraction) as u64 };
+                if tokens == 0 {
+                    return;
+                }
+                let sol_out = quote_sell(position.dex, &pool, tokens, self.pumpfun_fee_bps);
+                position.tokens -= tokens;
+                position.proceeds += sol_out;
+                let closed = position.tokens == 0;
+                let pnl = position.proceeds as i64 - position.cost as i64;
+
+                let report = self.report.wallet(&order.wallet);
+                if let Some(target_price) = order.target_price {
+                    report.record_slippage((1.0 - sol_out as f64 / tokens as f64 / target_price) * 10_000.0);
+                }
+                if closed {
+                    report.record_close(pnl);
+                    self.positions.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Queues a full exit for positions that hit take profit, stop loss or max hold.
+    fn check_exits(&mut self) {
+        let fee_bps = self.pumpfun_fee_bps;
+        let mut triggered = vec![];
+        for ((wallet, mint), position) in self.positions.iter_mut() {
+            let Some(pool) = self.pools.get(&position.pool) else {
+                continue;
+            };
+            if position.exiting || position.cost == 0 {
+                continue;
+            }
+            let value = position.proceeds + quote_sell(position.dex, pool, position.tokens, fee_bps);
+            let gain = value as f64 / position.cost as f64 - 1.0;
+            let exits = self.profiles.get(wallet).and_then(|profile| profile.exits.as_ref()).unwrap_or(&self.config.exits);
+            let hit = exits.take_profit.is_some_and(|take_profit| gain >= take_profit)
+                || exits.stop_loss.is_some_and(|stop_loss| gain <= -stop_loss)
+                || exits.max_hold_slots.is_some_and(|max_hold| self.slot.saturating_sub(position.opened_slot) >= max_hold);
+            if hit {
+                position.exiting = true;
+                triggered.push(Order {
+                    landing: self.latency.schedule(&position.pool, self.slot),
+                    wallet: wallet.clone(),
+                    mint: mint.clone(),
+                    pool: position.pool.clone(),
+                    dex: position.dex,
+                    kind: OrderKind::Sell(1.0),
+                    target_price: None,
+                });
+            }
+        }
+        self.orders.extend(triggered);
+    }
+}
+
+fn quote_buy(dex: Dex, pool: &PoolState, lamports: u64, pumpfun_fee_bps: u64) -> u64 {
+    match dex {
+        Dex::Raydium => quote_exact_in(lamports, pool.sol_reserve, pool.token_reserve, RaydiumFees::default())
+            .map(|quote| quote.amount_out)
+            .unwrap_or(0),
+        Dex::Pumpfun => curve(pool).buy_quote(lamports, pumpfun_fee_bps, 0.0).map(|quote| quote.token_amount).unwrap_or(0),
+    }
+}
+
+fn quote_sell(dex: Dex, pool: &PoolState, tokens: u64, pumpfun_fee_bps: u64) -> u64 {
+    match dex {
+        Dex::Raydium => quote_exact_in(tokens, pool.token_reserve, pool.sol_reserve, RaydiumFees::default())
+            .map(|quote| quote.amount_out)
+            .unwrap_or(0),
+        Dex::Pumpfun => curve(pool).sell_quote(tokens, pumpfun_fee_bps, 0.0).map(|quote| quote.sol_output).unwrap_or(0),
+    }
+}
+
+/// Recorded trades only carry the virtual reserves, so the real token reserves do not
+/// cap simulated buys.
+fn curve(pool: &PoolState) -> BondingCurve {
+    BondingCurve {
+        virtual_sol_reserves: pool.sol_reserve,
+        virtual_token_reserves: pool.token_reserve,
+        real_token_reserves: u64::MAX,
+    }
+}
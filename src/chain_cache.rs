@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use log::warn;
+use solana_sdk::{hash::Hash, pubkey::Pubkey};
+use tokio::time::{sleep, Duration};
+use crate::config::ChainCacheConfig;
+use crate::rpc::ChainRpc;
+
+/// Pools whose fees are followed, the least recently traded are dropped beyond it
+const MAX_FEE_POOLS: usize = 32;
+
+#[derive(Debug, Default)]
+struct ChainState {
+    /// Blockhash, its last valid block height, and when it was fetched
+    blockhash: Option<(Hash, u64, Instant)>,
+    /// Compute unit prices of the recent slots, sorted
+    fees: Vec<u64>,
+    /// Same, per pool, of the transactions writing it; and when the pool was last traded
+    pool_fees: HashMap<Pubkey, (Vec<u64>, Instant)>,
+}
+
+/// Latest blockhash and recent prioritization fees, refreshed in the background so
+/// that building a copy transaction needs no RPC round trip. Fees are followed
+/// globally, and for the pools being traded, whose write lock is what copies
+/// compete for.
+pub struct ChainCache {
+    config: ChainCacheConfig,
+    rpc: Arc<dyn ChainRpc>,
+    state: Mutex<ChainState>,
+}
+
+impl ChainCache {
+    pub fn with_rpc(config: ChainCacheConfig, rpc: Arc<dyn ChainRpc>) -> Self {
+        Self { config, rpc, state: Mutex::new(ChainState::default()) }
+    }
+
+    fn state(&self) -> MutexGuard<'_, ChainState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Spawns the refresh loops of the blockhash and of the fees.
+    pub fn start(self: &Arc<Self>) {
+        let cache = self.clone();
+        tokio::spawn(async move {
+            loop {
+                cache.refresh_blockhash().await;
+                sleep(Duration::from_millis(cache.config.blockhash_ms)).await;
+            }
+        });
+        let cache = self.clone();
+        tokio::spawn(async move {
+            loop {
+                cache.refresh_fees().await;
+                sleep(Duration::from_millis(cache.config.fees_ms)).await;
+            }
+        });
+    }
+
+    async fn refresh_blockhash(&self) {
+        match self.rpc.get_latest_blockhash().await {
+            Ok((blockhash, last_valid_block_height)) => {
+                self.state().blockhash = Some((blockhash, last_valid_block_height, Instant::now()));
+            }
+            Err(e) => warn!("Failed to refresh the blockhash: {:#}", e),
+        }
+    }
+
+    /// Refreshes the global fees, then those of every followed pool.
+    async fn refresh_fees(&self) {
+        match self.rpc.get_recent_prioritization_fees(&[]).await {
+            Ok(mut fees) => {
+                fees.sort_unstable();
+                self.state().fees = fees;
+            }
+            Err(e) => warn!("Failed to refresh the prioritization fees: {:#}", e),
+        }
+        let pools: Vec<Pubkey> = self.state().pool_fees.keys().copied().collect();
+        for pool in pools {
+            self.refresh_pool(&pool).await;
+        }
+    }
+
+    /// Follows the fees of `pool` from now on, fetching them right away if it is new.
+    pub fn watch(self: &Arc<Self>, pool: Pubkey) {
+        {
+            let mut state = self.state();
+            if let Some((_, traded_at)) = state.pool_fees.get_mut(&pool) {
+                *traded_at = Instant::now();
+                return;
+            }
+            if state.pool_fees.len() >= MAX_FEE_POOLS {
+                let oldest = state.pool_fees.iter().min_by_key(|(_, (_, traded_at))| *traded_at).map(|(pool, _)| *pool);
+                if let Some(oldest) = oldest {
+                    state.pool_fees.remove(&oldest);
+                }
+            }
+            state.pool_fees.insert(pool, (vec![], Instant::now()));
+        }
+        let cache = self.clone();
+        tokio::spawn(async move { cache.refresh_pool(&pool).await });
+    }
+
+    async fn refresh_pool(&self, pool: &Pubkey) {
+        match self.rpc.get_recent_prioritization_fees(&[*pool]).await {
+            Ok(mut fees) => {
+                fees.sort_unstable();
+                // Unless it stopped being followed meanwhile
+                if let Some((pool_fees, _)) = self.state().pool_fees.get_mut(pool) {
+                    *pool_fees = fees;
+                }
+            }
+            Err(e) => warn!("Failed to refresh the prioritization fees of {}: {:#}", pool, e),
+        }
+    }
+
+    /// The latest blockhash and its last valid block height, unless it is older than
+    /// `max_blockhash_age_ms`.
+    pub fn blockhash(&self) -> Option<(Hash, u64)> {
+        self.state().blockhash
+            .filter(|(_, _, fetched_at)| fetched_at.elapsed() < Duration::from_millis(self.config.max_blockhash_age_ms))
+            .map(|(blockhash, last_valid_block_height, _)| (blockhash, last_valid_block_height))
+    }
+
+    /// `percentile` (0 to 100) of the compute unit prices paid over the recent slots
+    /// to write `pool`, or by any transaction until the pool's fees are known.
+    pub fn fee_percentile(&self, pool: &Pubkey, percentile: f64) -> Option<u64> {
+        let state = self.state();
+        let fees = match state.pool_fees.get(pool) {
+            Some((fees, _)) if !fees.is_empty() => fees,
+            _ => &state.fees,
+        };
+        let last = fees.len().checked_sub(1)?;
+        let index = (percentile.clamp(0.0, 100.0) / 100.0 * last as f64).round() as usize;
+        fees.get(index).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::MockRpc;
+
+    fn cache(rpc: MockRpc, max_blockhash_age_ms: u64) -> Arc<ChainCache> {
+        let config = ChainCacheConfig { blockhash_ms: 400, fees_ms: 2000, max_blockhash_age_ms };
+        Arc::new(ChainCache::with_rpc(config, Arc::new(rpc)))
+    }
+
+    #[tokio::test]
+    async fn serves_the_blockhash_until_it_is_too_old() {
+        let blockhash = (Hash::new_unique(), 1234);
+        let fresh = cache(MockRpc { blockhash, ..Default::default() }, 5000);
+        assert_eq!(fresh.blockhash(), None);
+        fresh.refresh_blockhash().await;
+        assert_eq!(fresh.blockhash(), Some(blockhash));
+
+        let stale = cache(MockRpc { blockhash, ..Default::default() }, 0);
+        stale.refresh_blockhash().await;
+        assert_eq!(stale.blockhash(), None);
+    }
+
+    #[tokio::test]
+    async fn prefers_the_fees_of_the_pool() {
+        let (pool, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let rpc = MockRpc {
+            fees: [(vec![], vec![30, 10, 20, 0, 40]), (vec![pool], vec![5000, 1000, 3000])].into_iter().collect(),
+            ..Default::default()
+        };
+        let cache = cache(rpc, 5000);
+        assert_eq!(cache.fee_percentile(&pool, 50.0), None);
+        cache.refresh_fees().await;
+        assert_eq!(cache.fee_percentile(&pool, 50.0), Some(20));
+
+        cache.watch(pool);
+        cache.refresh_fees().await;
+        assert_eq!(cache.fee_percentile(&pool, 0.0), Some(1000));
+        assert_eq!(cache.fee_percentile(&pool, 50.0), Some(3000));
+        assert_eq!(cache.fee_percentile(&pool, 100.0), Some(5000));
+        assert_eq!(cache.fee_percentile(&other, 100.0), Some(40));
+    }
+}
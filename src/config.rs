@@ -1,10 +1,12 @@
 use serde::{Deserialize, Serialize};
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use maplit::hashmap;
 use futures::{Sink, Stream, channel::mpsc, SinkExt};
 use yellowstone_grpc_client::{GeyserGrpcClient, Interceptor};
 use yellowstone_grpc_proto::{prelude::*, tonic::{Status, transport::channel::ClientTlsConfig}};
 use std::path::Path;
+use crate::targets::TARGETS;
+use crate::trade::{Dex, Side};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
@@ -13,6 +15,17 @@ pub struct Config {
     pub wallets: WalletConfig,
     pub trade_settings: TradeSettings,
     pub dex: DexConfig,
+    pub commitment: CommitmentConfig,
+    pub backfill: BackfillConfig,
+    pub recorder: RecorderConfig,
+    pub replay: ReplayConfig,
+    pub backtest: BacktestConfig,
+    pub pools: PoolsConfig,
+    pub execution: ExecutionConfig,
+    pub safety: SafetyConfig,
+    pub filters: FiltersConfig,
+    pub risk: RiskConfig,
+    pub logging: LoggingConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -22,19 +35,152 @@ pub struct RpcConfig {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GrpcConfig {
+    /// Ordered by preference, the first entry is the primary endpoint
+    pub endpoints: Vec<GrpcEndpoint>,
+    pub mode: FeedMode,
+    pub reconnect: ReconnectConfig,
+    /// How long a signature is remembered to drop copies delivered by other feeds
+    pub dedup_window_secs: u64,
+    pub dedup_capacity: usize,
+    pub resume: ResumeConfig,
+}
+
+/// Persists the last processed slot so reconnects resubscribe with `from_slot` and
+/// replay what was missed while disconnected.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResumeConfig {
+    pub enabled: bool,
+    pub state_dir: String,
+}
+
+/// `failover` keeps one subscription and walks the endpoint list when it fails,
+/// `redundant` subscribes to every endpoint at once and keeps the first copy of
+/// each transaction.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedMode {
+    Failover,
+    Redundant,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GrpcEndpoint {
     pub endpoint: String,
     pub x_token: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReconnectConfig {
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub multiplier: f64,
+    /// Fraction of each delay that is randomized, between 0 and 1
+    pub jitter: f64,
+    /// Consecutive failures on an endpoint before failing over to the next one
+    pub failover_after: u32,
+    /// How often to probe the primary endpoint while connected to a fallback
+    pub failback_probe_secs: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct WalletConfig {
-    pub targets: Vec<String>,
+    pub targets: Vec<TargetProfile>,
+    /// How often `targets` are re-read from the config file, 0 to never. Added
+    /// targets are backfilled and subscribed to.
+    pub reload_secs: u64,
     pub copier: CopierWallet,
 }
 
+impl WalletConfig {
+    /// Enabled targets with a wallet set, the ones that are subscribed to and copied.
+    pub fn active_targets(&self) -> impl Iterator<Item = &TargetProfile> {
+        self.targets.iter().filter(|target| target.enabled && !target.wallet.is_empty())
+    }
+}
+
+/// A followed wallet and how it is copied. Unset overrides fall back to the global
+/// settings: buys mirror the target's SOL amount within the entry limits, and exits
+/// are those of `backtest.exits` (live, only `follow_target_sells` applies).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TargetProfile {
+    pub wallet: String,
+    pub label: String,
+    pub enabled: bool,
+    pub sizing: Option<SizingConfig>,
+    /// Most SOL put into one mint by copies of this target
+    pub max_position: Option<f64>,
+    pub dexes: Vec<Dex>,
+    pub sides: CopySides,
+    pub exits: Option<ExitConfig>,
+    pub priority_fee: PriorityFeeTier,
+    pub mints: MintLists,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CopySides {
+    Buy,
+    Sell,
+    Both,
+}
+
+impl CopySides {
+    pub fn allows(self, side: Side) -> bool {
+        match self {
+            CopySides::Buy => side == Side::Buy,
+            CopySides::Sell => side == Side::Sell,
+            CopySides::Both => true,
+        }
+    }
+}
+
+/// How hard copies of a target compete for inclusion.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PriorityFeeTier {
+    Low,
+    Medium,
+    High,
+}
+
+/// Where the copier key comes from: `keypair_path`, a Solana CLI keypair JSON file;
+/// `key_env`, an environment variable holding the base58 key; or `keystore`, a file
+/// encrypted with a passphrase (see `encrypt-key`). A `private_key` written in the
+/// config itself is refused unless `allow_plaintext_key` is set.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CopierWallet {
-    pub private_key: String,
+    pub keypair_path: Option<String>,
+    pub key_env: Option<String>,
+    pub keystore: Option<KeystoreConfig>,
+    pub private_key: Secret,
+    pub allow_plaintext_key: bool,
+}
+
+/// The passphrase is read from the `passphrase_env` environment variable.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeystoreConfig {
+    pub path: String,
+    pub passphrase_env: String,
+}
+
+/// A secret read from the config, redacted from `Debug` output.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.is_empty() {
+            true => f.write_str("Secret(\"\")"),
+            false => f.write_str("Secret(<redacted>)"),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -58,6 +204,237 @@ pub struct RaydiumConfig {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PumpfunConfig {
     pub program_id: String,
+    /// Trade fee of the global account until a `SetParams` says otherwise
+    pub fee_basis_points: u64,
+}
+
+/// Trades are acted on at processed commitment, then followed until their slot
+/// reaches `confirm_at`, or turns out dead or skipped.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CommitmentConfig {
+    pub confirm_at: ConfirmLevel,
+    pub on_fork: ForkAction,
+    /// Slots after which a trade whose slot never got confirmed is considered dropped
+    pub max_unconfirmed_slots: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmLevel {
+    Confirmed,
+    Finalized,
+}
+
+/// What to do when a copied trade's slot is dead or skipped.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForkAction {
+    Alert,
+    /// Also send the reverse of our landed copy of the trade
+    Unwind,
+}
+
+/// On startup, and when a target is added, the recent history of the target is
+/// fetched over RPC to rebuild its open positions.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackfillConfig {
+    pub enabled: bool,
+    pub signatures_per_wallet: usize,
+    /// Delay between `getTransaction` calls, to stay under RPC rate limits
+    pub request_delay_ms: u64,
+}
+
+/// Writes every raw gRPC update to hourly files in `dir`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecorderConfig {
+    pub enabled: bool,
+    pub dir: String,
+}
+
+/// Replaces the gRPC feeds with the recordings found in `dir`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReplayConfig {
+    pub enabled: bool,
+    pub dir: String,
+    /// 1.0 replays at the original pace, 10.0 ten times faster, 0 without waiting
+    pub speed: f64,
+}
+
+/// Copy strategy simulated by `cargo run -- backtest` over the recordings in `dir`.
+/// Entries are clamped to `trade_settings.min_entry`/`max_entry`, and target buys
+/// are screened with the size and price impact thresholds of `filters`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BacktestConfig {
+    pub dir: String,
+    pub sizing: SizingConfig,
+    pub latency: LatencyConfig,
+    pub exits: ExitConfig,
+}
+
+/// `fixed` buys `amount` SOL per copy, `ratio` buys `amount` times the target's size.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum SizingConfig {
+    Fixed { amount: f64 },
+    Ratio { amount: f64 },
+}
+
+/// When a copy lands relative to the trade it copies. `slots` fills against the pool
+/// state `slots` later; `swaps` fills after that many more swaps on the same pool, or
+/// after `max_slots` if the pool goes quiet. Only swaps present in the recordings are
+/// counted.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum LatencyConfig {
+    Slots { slots: u64 },
+    Swaps { swaps: u64, max_slots: u64 },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExitConfig {
+    /// Sell everything once the position is up this fraction, e.g. 1.0 for +100%
+    pub take_profit: Option<f64>,
+    /// Sell everything once the position is down this fraction, e.g. 0.5 for -50%
+    pub stop_loss: Option<f64>,
+    /// Sell the same fraction of the position as the target sells of its own
+    pub follow_target_sells: bool,
+    pub max_hold_slots: Option<u64>,
+}
+
+/// The pool-state cache is fed by decoded trades; with `subscribe_accounts` the feeds
+/// also subscribe to the bonding curves and Raydium vaults of the `max_watched` most
+/// recently traded pools, refreshed every `refresh_secs` when new pools show up.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PoolsConfig {
+    pub subscribe_accounts: bool,
+    pub max_watched: usize,
+    pub refresh_secs: u64,
+}
+
+/// Raydium swaps go through the copier's WSOL account. By default it is funded before
+/// each buy and closed after each swap; `persistent_wsol` keeps it open instead, which
+/// saves the rent round trip and two instructions per copy. Copies set a compute unit
+/// limit and a price from their profile's priority fee tier.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExecutionConfig {
+    pub persistent_wsol: bool,
+    pub compute_unit_limit: u32,
+    pub priority_fees: PriorityFees,
+    pub chain_cache: ChainCacheConfig,
+    pub confirmation: ConfirmationConfig,
+    pub sender: SenderConfig,
+}
+
+/// Where copies are submitted: `rpc` to the configured RPC endpoint, `fanout` to
+/// every URL at once, `bundle` to a block engine with a tip of `tip_lamports` to
+/// `tip_account`, and `mock` nowhere, recording them instead.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum SenderConfig {
+    Rpc { skip_preflight: bool },
+    Fanout { urls: Vec<String>, skip_preflight: bool },
+    Bundle { url: String, tip_account: String, tip_lamports: u64 },
+    Mock,
+}
+
+/// Each priority fee tier is a percentile (0 to 100) of the compute unit prices paid
+/// over the recent slots, bounded by `min_price` and `max_price` in micro-lamports.
+/// `min_price` is used until the first fees are fetched.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PriorityFees {
+    pub low: f64,
+    pub medium: f64,
+    pub high: f64,
+    pub min_price: u64,
+    pub max_price: u64,
+}
+
+impl PriorityFees {
+    pub fn percentile(&self, tier: PriorityFeeTier) -> f64 {
+        match tier {
+            PriorityFeeTier::Low => self.low,
+            PriorityFeeTier::Medium => self.medium,
+            PriorityFeeTier::High => self.high,
+        }
+    }
+}
+
+/// The blockhash is refreshed every `blockhash_ms` and the prioritization fees, global
+/// and of the recently traded pools, every `fees_ms`. A blockhash older than `max_blockhash_age_ms` is fetched when sending.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChainCacheConfig {
+    pub blockhash_ms: u64,
+    pub fees_ms: u64,
+    pub max_blockhash_age_ms: u64,
+}
+
+/// Submitted copies are polled every `poll_ms` until they land or their blockhash
+/// expires. Expired copies are re-signed with a fresh blockhash and a compute unit
+/// price `fee_bump` times higher, at most `max_resends` times.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConfirmationConfig {
+    pub poll_ms: u64,
+    pub max_resends: usize,
+    pub fee_bump: f64,
+}
+
+/// Rug and honeypot checks before copying a buy, cached per mint for `cache_secs`.
+/// A mint is blocked when its mint or freeze authority is active, when its
+/// `top_holders` largest holders besides the pool own more than
+/// `max_top_holders_share` of the supply, when less than `min_lp_burned` of a Raydium
+/// pool's LP is burned, or when the Pump.fun creator launched more than
+/// `max_creator_launches` other mints in its last `creator_history` transactions.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SafetyConfig {
+    pub enabled: bool,
+    pub allow_mint_authority: bool,
+    pub allow_freeze_authority: bool,
+    pub top_holders: usize,
+    pub max_top_holders_share: f64,
+    pub min_lp_burned: f64,
+    pub max_creator_launches: usize,
+    pub creator_history: usize,
+    pub cache_secs: u64,
+}
+
+/// Which target buys get copied. A mint must not be on the global or the target
+/// profile's denylist, and must be on every non-empty allowlist. The pool's SOL reserve is the
+/// virtual reserve for Pump.fun. Token age is the Raydium pool's open time or the
+/// Pump.fun bonding curve's creation.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FiltersConfig {
+    pub mints: MintLists,
+    pub min_target_sol: f64,
+    pub min_pool_sol: f64,
+    pub max_price_impact: f64,
+    pub max_token_age_secs: Option<u64>,
+}
+
+/// Global limits on copied buys, in SOL. Buys are trimmed to the room left under
+/// `max_exposure` and `max_per_mint`. Once the day's (UTC) realized loss reaches
+/// `daily_loss_limit`, buying halts until a restart with `reset_halt` set. The
+/// exposure, the buys of the last minute and the day's PnL are kept in `state_dir`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RiskConfig {
+    pub state_dir: String,
+    pub max_exposure: f64,
+    pub max_positions: usize,
+    pub max_per_mint: f64,
+    pub max_buys_per_minute: usize,
+    pub daily_loss_limit: f64,
+    pub reset_halt: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MintLists {
+    pub allowlist: Vec<String>,
+    pub denylist: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoggingConfig {
+    pub undecoded_path: String,
+    pub metrics_interval_secs: u64,
 }
 
 impl Config {
@@ -65,30 +442,35 @@ impl Config {
         let config_path = Path::new("config/default.json");
         let config_str = std::fs::read_to_string(config_path)?;
         let config: Config = serde_json::from_str(&config_str)?;
+        if config.grpc.endpoints.is_empty() {
+            return Err(anyhow!("At least one gRPC endpoint must be configured"));
+        }
         Ok(config)
     }
 
-    async fn connect_grpc(&self) -> Result<GeyserGrpcClient<impl Interceptor>> {
-        let url  = self.get_grpc_url();
+    pub async fn connect_grpc(&self, endpoint: &GrpcEndpoint) -> Result<GeyserGrpcClient<impl Interceptor>> {
+        let url  = endpoint.endpoint.clone();
         println!("Connecting to {}", url);
-        let client = GeyserGrpcClient::build_from_shared(url).expect("Failed to build GeyserGrpcClient")
-            .x_token(Some(self.grpc.x_token.clone())).expect("Failed to Auth x_token")
-            .tls_config(ClientTlsConfig::new().with_native_roots()).expect("Error tls config")
+        // A misconfigured endpoint fails like an unreachable one, so the monitor moves on
+        let client = GeyserGrpcClient::build_from_shared(url.clone()).with_context(|| format!("Invalid gRPC endpoint {}", url))?
+            .x_token(Some(endpoint.x_token.clone())).with_context(|| format!("Invalid x_token for {}", url))?
+            .tls_config(ClientTlsConfig::new().with_native_roots()).with_context(|| format!("Invalid TLS config for {}", url))?
             .connect()
             .await?;
         Ok(client)
     }
 
-    fn build_request_monitor_wallet(&self, selector: u8) -> Result<SubscribeRequest> {
+    pub fn build_request_monitor_wallet(&self, selector: u8, from_slot: Option<u64>, pool_accounts: Vec<String>) -> Result<SubscribeRequest> {
         let required_accounts = match selector {
             0 => self.dex.raydium.program_id.clone(),
             1 => self.dex.pumpfun.program_id.clone(),
             _ => return Err(anyhow!("Invalid selector"))   
         };
         let request = SubscribeRequest {
+            // Every slot status is needed to follow trades from processed to confirmed
             slots: hashmap!{
                 "".to_owned() => SubscribeRequestFilterSlots{
-                    filter_by_commitment: Some(true),
+                    filter_by_commitment: Some(false),
                     interslot_updates: Some(true)
                 }
             },
@@ -96,20 +478,30 @@ impl Config {
                 "".to_owned() => SubscribeRequestFilterTransactions {
                     vote: Some(false),
                     failed: Some(false),
-                    account_include: self.wallets.targets.clone(),
+                    account_include: TARGETS.active_wallets(),
                     account_required:  vec![
                         required_accounts
                     ],
                     ..Default::default()
                 }
             },
+            accounts: match pool_accounts.is_empty() {
+                true => hashmap!{},
+                false => hashmap!{
+                    "pools".to_owned() => SubscribeRequestFilterAccounts {
+                        account: pool_accounts,
+                        ..Default::default()
+                    }
+                },
+            },
             commitment: Some(CommitmentLevel::Processed as i32),
+            from_slot,
             ..Default::default()
         };
         Ok(request)
     }
 
-    async fn subscribe(&self, client: &mut GeyserGrpcClient<impl Interceptor>, selector: u8) -> Result<(
+    async fn subscribe(&self, client: &mut GeyserGrpcClient<impl Interceptor>, selector: u8, from_slot: Option<u64>, pool_accounts: Vec<String>) -> Result<(
         impl Sink<SubscribeRequest, Error = mpsc::SendError>,
         impl Stream<Item = Result<SubscribeUpdate, Status>>,
     )> {
@@ -117,7 +509,7 @@ impl Config {
             .await
             .map_err(|e| anyhow!("Failed to subscribe to GeyserGrpcClient: {}", e))?;
 
-        let request = self.build_request_monitor_wallet(selector)?;
+        let request = self.build_request_monitor_wallet(selector, from_slot, pool_accounts)?;
 
         tx.send(request)
             .await
@@ -126,16 +518,12 @@ impl Config {
         Ok((tx, rx))
     }
 
-    pub async fn grpc_monitor(&self, selector: u8) -> Result<(
+    pub async fn grpc_monitor(&self, endpoint: &GrpcEndpoint, selector: u8, from_slot: Option<u64>, pool_accounts: Vec<String>) -> Result<(
         impl Sink<SubscribeRequest, Error = mpsc::SendError>,
         impl Stream<Item = Result<SubscribeUpdate, Status>>,
     )> {
-        let mut client = self.connect_grpc().await?;
-        self.subscribe(&mut client, selector).await
-    }
-
-    pub fn get_grpc_url(&self) -> String {
-        self.grpc.endpoint.clone()
+        let mut client = self.connect_grpc(endpoint).await?;
+        self.subscribe(&mut client, selector, from_slot, pool_accounts).await
     }
 }
 
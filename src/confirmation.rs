@@ -0,0 +1,117 @@
+use std::sync::Arc;
+use log::warn;
+use solana_sdk::signature::Signature;
+use tokio::time::{sleep, Duration};
+use crate::rpc::ChainRpc;
+
+/// What a landed copy actually moved. Buys: tokens received and lamports spent;
+/// sells: tokens sold and lamports received. Network fees and rent are included.
+#[derive(Debug, Clone, Copy)]
+pub struct Fill {
+    pub tokens: u64,
+    pub lamports: u64,
+}
+
+/// Final state of a submitted copy.
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    /// `fill` is `None` if the landed transaction could not be fetched
+    Landed { signature: Signature, slot: u64, fill: Option<Fill> },
+    /// Failed on chain, or could not be built or sent (no signature then)
+    Failed { signature: Option<Signature>, error: String },
+    /// The blockhash of every attempt expired before it landed
+    Expired { attempts: usize },
+}
+
+/// What became of one signed attempt.
+#[derive(Debug, Clone)]
+pub enum Tracked {
+    Landed(u64),
+    Failed(String),
+    Expired,
+}
+
+/// Follows our own signatures with `getSignatureStatuses` until they land, fail, or
+/// can no longer land.
+pub struct ConfirmationTracker {
+    rpc: Arc<dyn ChainRpc>,
+    poll: Duration,
+}
+
+impl ConfirmationTracker {
+    pub fn with_rpc(rpc: Arc<dyn ChainRpc>, poll: Duration) -> Self {
+        Self { rpc, poll }
+    }
+
+    /// Polls `signature` until it is confirmed, or until the chain is past
+    /// `last_valid_block_height` without having seen it. Processed transactions are
+    /// followed past the expiry, since they may still be confirmed. RPC errors are
+    /// retried on the next poll.
+    pub async fn track(&self, signature: &Signature, last_valid_block_height: u64) -> Tracked {
+        loop {
+            // Read before the status, so an unseen signature at this height is dead
+            let block_height = self.rpc.get_block_height().await;
+            match self.rpc.get_signature_statuses(&[*signature]).await {
+                Ok(statuses) => match statuses.into_iter().next().flatten() {
+                    Some(status) if status.err.is_some() => {
+                        return Tracked::Failed(status.err.map(|err| err.to_string()).unwrap_or_default());
+                    }
+                    Some(status) if matches!(status.confirmation_status.as_deref(), Some("confirmed" | "finalized")) => {
+                        return Tracked::Landed(status.slot);
+                    }
+                    Some(_) => {}
+                    None => match block_height {
+                        Ok(height) if height > last_valid_block_height => return Tracked::Expired,
+                        Ok(_) => {}
+                        Err(e) => warn!("Failed to get the block height: {:#}", e),
+                    },
+                },
+                Err(e) => warn!("Failed to get the status of {}: {:#}", signature, e),
+            }
+            sleep(self.poll).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::rpc::{MockRpc, SignatureStatus};
+
+    const LAST_VALID_BLOCK_HEIGHT: u64 = 100;
+
+    fn status(confirmation_status: &str, err: Option<serde_json::Value>) -> Option<SignatureStatus> {
+        Some(SignatureStatus { slot: 42, err, confirmation_status: Some(confirmation_status.to_owned()) })
+    }
+
+    async fn track(block_height: u64, statuses: Vec<Option<SignatureStatus>>) -> Tracked {
+        let rpc = MockRpc { block_height, statuses: std::sync::Mutex::new(statuses.into()), ..Default::default() };
+        let tracker = ConfirmationTracker::with_rpc(Arc::new(rpc), Duration::ZERO);
+        tracker.track(&Signature::default(), LAST_VALID_BLOCK_HEIGHT).await
+    }
+
+    #[tokio::test]
+    async fn lands_once_confirmed() {
+        let tracked = track(50, vec![None, status("processed", None), status("confirmed", None)]).await;
+        assert!(matches!(tracked, Tracked::Landed(42)));
+    }
+
+    #[tokio::test]
+    async fn fails_on_a_transaction_error() {
+        let tracked = track(50, vec![status("processed", Some(json!({ "InstructionError": [2, "Custom"] })))]).await;
+        assert!(matches!(tracked, Tracked::Failed(error) if error.contains("InstructionError")));
+    }
+
+    #[tokio::test]
+    async fn expires_unseen_past_the_last_valid_block_height() {
+        let tracked = track(LAST_VALID_BLOCK_HEIGHT + 1, vec![]).await;
+        assert!(matches!(tracked, Tracked::Expired));
+    }
+
+    #[tokio::test]
+    async fn follows_processed_transactions_past_the_expiry() {
+        let tracked = track(LAST_VALID_BLOCK_HEIGHT + 1, vec![status("processed", None), status("finalized", None)]).await;
+        assert!(matches!(tracked, Tracked::Landed(42)));
+    }
+}
@@ -0,0 +1,675 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use log::{error, info, warn};
+use crate::confirmation::{Fill, Outcome};
+use crate::config::{Config, ForkAction, PriorityFeeTier, SizingConfig, TargetProfile, TradeSettings};
+use crate::copy_filter::CopyFilter;
+use crate::execution::Executor;
+use crate::metrics::{Metrics, METRICS};
+use crate::mints::MintResolver;
+use crate::positions::PositionBook;
+use crate::pools::{PoolState, POOLS};
+use crate::quote::pumpfun::{fee_basis_points, BondingCurve};
+use crate::quote::raydium::{minimum_out, quote_exact_in};
+use crate::resolver::PoolResolver;
+use crate::risk::{BuyReservation, RiskManager};
+use crate::safety::{SafetyGate, Verdict};
+use crate::targets::TARGETS;
+use crate::trade::{Dex, Side, TradeEvent};
+use solana_sdk::pubkey::Pubkey;
+
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+
+/// The swap we send to mirror a target trade, quoted against the latest known state
+/// of the pool.
+#[derive(Debug, Clone, Copy)]
+pub struct CopyOrder {
+    pub dex: Dex,
+    /// AMM id or bonding curve
+    pub pool: Pubkey,
+    pub mint: Pubkey,
+    pub side: Side,
+    pub amount_in: u64,
+    pub expected_out: u64,
+    pub limit: OrderLimit,
+    pub fee: u64,
+    pub priority_fee: PriorityFeeTier,
+}
+
+/// Slippage guard of an order: Raydium swaps and Pump.fun sells fix the input and
+/// bound the output, Pump.fun buys fix the tokens out and bound the SOL in.
+#[derive(Debug, Clone, Copy)]
+pub enum OrderLimit {
+    MinimumOut(u64),
+    MaxSolCost(u64),
+}
+
+/// What our landed copies of one target hold of one mint.
+#[derive(Debug, Default, Clone, Copy)]
+struct Holding {
+    tokens: u64,
+    /// Tokens held back for sells in flight
+    selling: u64,
+    /// Lamports spent buying the tokens still held, selling included
+    cost: u64,
+}
+
+/// Our copy of a target trade, followed until the trade is confirmed or dropped so
+/// that a copy of a dropped trade can be unwound.
+#[derive(Debug, Clone)]
+enum CopyState {
+    InFlight,
+    Landed(Fill),
+    /// The target trade was dropped before our copy reached its final state
+    Orphaned(TradeEvent),
+}
+
+/// Decides what the bot does with the target trades detected by the monitors.
+pub struct Copier {
+    on_fork: ForkAction,
+    trade_settings: TradeSettings,
+    positions: Arc<Mutex<PositionBook>>,
+    resolver: Arc<PoolResolver>,
+    mints: Arc<MintResolver>,
+    filter: CopyFilter,
+    /// Rug and honeypot checks of the mints of target buys, when enabled
+    safety: Option<Arc<SafetyGate>>,
+    risk: Arc<RiskManager>,
+    /// Sends orders and tracks them; without a copier key they are only logged
+    executor: Option<Arc<Executor>>,
+    /// Per (target, mint), from our own copies
+    holdings: Mutex<HashMap<(String, String), Holding>>,
+    /// Per target signature, with `on_fork` set to unwind
+    copies: Mutex<HashMap<String, CopyState>>,
+}
+
+impl Copier {
+    pub fn new(
+        config: &Config,
+        positions: Arc<Mutex<PositionBook>>,
+        resolver: Arc<PoolResolver>,
+        mints: Arc<MintResolver>,
+        risk: Arc<RiskManager>,
+        executor: Option<Arc<Executor>>,
+    ) -> Self {
+        Self {
+            on_fork: config.commitment.on_fork,
+            trade_settings: config.trade_settings.clone(),
+            positions,
+            filter: CopyFilter::new(config),
+            safety: config.safety.enabled.then(|| Arc::new(SafetyGate::new(config, resolver.clone()))),
+            resolver,
+            mints,
+            risk,
+            executor,
+            holdings: Mutex::new(HashMap::new()),
+            copies: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn positions(&self) -> MutexGuard<'_, PositionBook> {
+        match self.positions.lock() {
+            Ok(positions) => positions,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Called as soon as a target trade is seen at processed commitment.
+    pub fn on_target_trade(self: &Arc<Self>, trade: &TradeEvent) {
+        Metrics::incr(&METRICS.trades_detected);
+        let trade = &self.mints.received(trade);
+        // A sell is mirrored as the same fraction of the position the target held
+        let sell_fraction = {
+            let mut positions = self.positions();
+            let fraction = match trade.side {
+                Side::Sell => positions.position(&trade.wallet, &trade.mint)
+                    .map(|position| (trade.token_amount as f64 / position.token_amount.max(1) as f64).min(1.0)),
+                Side::Buy => None,
+            };
+            positions.apply_live(trade);
+            fraction
+        };
+        info!(
+            "COPY {:?} {:?} {} of {} by {} at slot {} ({})",
+            trade.dex, trade.side, trade.sol_amount, trade.mint, trade.wallet, trade.slot, trade.signature
+        );
+        if let Some(fraction) = sell_fraction {
+            info!("Target sold {:.1}% of its {} position", fraction * 100.0, trade.mint);
+        }
+        if trade.dex == Dex::Raydium {
+            self.prefetch_pool(&trade.pool);
+        }
+        self.prefetch_mint(&trade.mint);
+        if let Some(executor) = &self.executor {
+            executor.watch_fees(&trade.pool);
+        }
+
+        if let Some(reason) = self.excluded(trade) {
+            info!("SKIP copy of {} ({}): {}", trade.signature, trade.wallet, reason);
+            return;
+        }
+        if trade.side == Side::Sell {
+            self.copy(trade, sell_fraction);
+            return;
+        }
+        if let Some(reason) = self.filter.screen(trade) {
+            self.block(trade, &reason);
+            return;
+        }
+        let (copier, trade) = (self.clone(), trade.clone());
+        tokio::spawn(async move {
+            match copier.vet(&trade).await {
+                Some(reason) => copier.block(&trade, &reason),
+                None => copier.copy(&trade, None),
+            }
+        });
+    }
+
+    /// Trades the target's profile does not copy, and trades of wallets that are not
+    /// targets, like the other party of a transaction a target is part of.
+    fn excluded(&self, trade: &TradeEvent) -> Option<String> {
+        let Some(profile) = self.profile(&trade.wallet) else {
+            return Some("not a target".to_owned());
+        };
+        if !profile.enabled {
+            return Some(format!("target {} is disabled", profile.label));
+        }
+        if !profile.dexes.contains(&trade.dex) {
+            return Some(format!("target {} is not copied on {:?}", profile.label, trade.dex));
+        }
+        if !profile.sides.allows(trade.side) {
+            return Some(format!("target {} is not copied on {:?}s", profile.label, trade.side));
+        }
+        if trade.side == Side::Sell && profile.exits.as_ref().is_some_and(|exits| !exits.follow_target_sells) {
+            return Some(format!("target {} sells are not followed", profile.label));
+        }
+        None
+    }
+
+    fn profile(&self, wallet: &str) -> Option<TargetProfile> {
+        TARGETS.profile(wallet)
+    }
+
+    /// The checks of a buy that need RPC, all cached per mint: token age, then safety.
+    async fn vet(&self, trade: &TradeEvent) -> Option<String> {
+        if let Some(reason) = self.filter.check_age(trade).await {
+            return Some(reason);
+        }
+        match &self.safety {
+            Some(safety) => match safety.check(trade).await {
+                Verdict::Safe => None,
+                Verdict::Blocked(reason) => Some(reason),
+            },
+            None => None,
+        }
+    }
+
+    fn copy(self: &Arc<Self>, trade: &TradeEvent, sell_fraction: Option<f64>) {
+        if let Some((order, reservation)) = self.plan(trade, sell_fraction) {
+            let limit = match order.limit {
+                OrderLimit::MinimumOut(amount) => format!("minimum_out {}", amount),
+                OrderLimit::MaxSolCost(amount) => format!("max_sol_cost {}", amount),
+            };
+            info!(
+                "ORDER {:?} {} in for {} out ({}, fee {}, {:?} priority) on {}",
+                order.side, order.amount_in, order.expected_out, limit, order.fee, order.priority_fee, trade.pool
+            );
+            self.execute(&trade.wallet, order, reservation, Some(&trade.signature));
+        }
+    }
+
+    fn block(&self, trade: &TradeEvent, reason: &str) {
+        Metrics::incr(&METRICS.copies_blocked);
+        warn!("BLOCKED copy of {} by {} ({}): {}", trade.mint, trade.wallet, trade.signature, reason);
+    }
+
+    fn holdings(&self) -> MutexGuard<'_, HashMap<(String, String), Holding>> {
+        match self.holdings.lock() {
+            Ok(holdings) => holdings,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn copies(&self) -> MutexGuard<'_, HashMap<String, CopyState>> {
+        match self.copies.lock() {
+            Ok(copies) => copies,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Sends the order and books it at its final state. While in flight, a buy holds
+    /// its risk reservation and a sell holds back its tokens. `copy_of` is the target
+    /// signature the order mirrors, none for unwinds.
+    fn execute(self: &Arc<Self>, wallet: &str, order: CopyOrder, reservation: Option<BuyReservation>, copy_of: Option<&str>) {
+        let Some(executor) = self.executor.clone() else {
+            return;
+        };
+        let key = (wallet.to_owned(), order.mint.to_string());
+        match order.side {
+            Side::Buy if reservation.is_none() => {
+                warn!("Dropping buy of {} without a risk reservation", order.mint);
+                return;
+            }
+            Side::Buy => {}
+            Side::Sell => {
+                let mut holdings = self.holdings();
+                let holding = holdings.entry(key.clone()).or_default();
+                // Another sell of the same holding went out since this one was planned
+                if holding.tokens < order.amount_in {
+                    warn!("Dropping sell of {} {}: only {} left", order.amount_in, order.mint, holding.tokens);
+                    return;
+                }
+                holding.tokens -= order.amount_in;
+                holding.selling += order.amount_in;
+            }
+        }
+        let copy_of = copy_of.filter(|_| self.on_fork == ForkAction::Unwind).map(str::to_owned);
+        if let Some(signature) = &copy_of {
+            self.copies().insert(signature.clone(), CopyState::InFlight);
+        }
+        let copier = self.clone();
+        tokio::spawn(async move {
+            let outcome = executor.execute(&order).await;
+            let fill = copier.settle(&key, &order, &outcome, reservation);
+            if let Some(signature) = copy_of {
+                copier.follow(&signature, fill);
+            }
+            match outcome {
+                Outcome::Landed { signature, slot, .. } => {
+                    Metrics::incr(&METRICS.copies_landed);
+                    info!("LANDED {:?} of {} as {} at slot {}", order.side, order.mint, signature, slot);
+                }
+                Outcome::Failed { signature, error } => {
+                    Metrics::incr(&METRICS.copies_failed);
+                    let signature = signature.map(|signature| signature.to_string()).unwrap_or_else(|| "unsent".to_owned());
+                    warn!("FAILED {:?} of {} ({}): {}", order.side, order.mint, signature, error);
+                }
+                Outcome::Expired { attempts } => {
+                    Metrics::incr(&METRICS.copies_expired);
+                    warn!("EXPIRED {:?} of {} after {} attempts", order.side, order.mint, attempts);
+                }
+            }
+        });
+    }
+
+    /// Books a landed copy at its fill (at its quote if the fill could not be read),
+    /// and releases what a copy that did not land held. Returns the fill if it landed.
+    fn settle(
+        &self,
+        key: &(String, String),
+        order: &CopyOrder,
+        outcome: &Outcome,
+        reservation: Option<BuyReservation>,
+    ) -> Option<Fill> {
+        let fill = match outcome {
+            Outcome::Landed { fill: Some(fill), .. } => Some(*fill),
+            Outcome::Landed { fill: None, slot, .. } => Some(match order.side {
+                Side::Buy => Fill {
+                    tokens: order.expected_out - self.mints.transfer_fee(&key.1, order.expected_out, *slot),
+                    lamports: order.amount_in,
+                },
+                Side::Sell => Fill { tokens: order.amount_in, lamports: order.expected_out },
+            }),
+            Outcome::Failed { .. } | Outcome::Expired { .. } => None,
+        };
+        let mut holdings = self.holdings();
+        let holding = holdings.entry(key.clone()).or_default();
+        let spent = fill.map_or(0, |fill| fill.lamports);
+        if let Some(reservation) = reservation {
+            reservation.settle(spent);
+        }
+        match (order.side, fill) {
+            (Side::Buy, Some(fill)) => {
+                holding.tokens += fill.tokens;
+                holding.cost += fill.lamports;
+            }
+            (Side::Buy, None) => {}
+            (Side::Sell, Some(fill)) => {
+                let held = holding.tokens + holding.selling;
+                let cost = (holding.cost as u128 * order.amount_in as u128 / held.max(1) as u128) as u64;
+                holding.selling -= order.amount_in;
+                holding.cost -= cost;
+                self.risk.record_sell(&key.1, cost, fill.lamports as i64 - cost as i64);
+            }
+            (Side::Sell, None) => {
+                holding.selling -= order.amount_in;
+                holding.tokens += order.amount_in;
+            }
+        }
+        fill
+    }
+
+    /// Records the final state of our copy of `signature`, and unwinds it right away
+    /// if the target trade was dropped while it was in flight.
+    fn follow(self: &Arc<Self>, signature: &str, fill: Option<Fill>) {
+        let state = self.copies().remove(signature);
+        match (state, fill) {
+            (Some(CopyState::InFlight), Some(fill)) => {
+                self.copies().insert(signature.to_owned(), CopyState::Landed(fill));
+            }
+            (Some(CopyState::Orphaned(trade)), Some(fill)) => self.unwind(&trade, fill),
+            _ => {}
+        }
+    }
+
+    /// Resolves the pool's swap accounts in the background, so they are at hand when
+    /// we exit even if the target never trades the pool again.
+    fn prefetch_pool(&self, pool: &str) {
+        let Ok(pool) = Pubkey::from_str(pool) else {
+            return;
+        };
+        if self.resolver.cached(&pool).is_some() {
+            return;
+        }
+        let resolver = self.resolver.clone();
+        tokio::spawn(async move {
+            if let Err(e) = resolver.resolve(&pool).await {
+                warn!("Failed to resolve Raydium pool {}: {:#}", pool, e);
+            }
+        });
+    }
+
+    /// Fetches the mint's token program and transfer fees in the background.
+    fn prefetch_mint(&self, mint: &str) {
+        let Ok(mint) = Pubkey::from_str(mint) else {
+            return;
+        };
+        if self.mints.cached(&mint).is_some() {
+            return;
+        }
+        let mints = self.mints.clone();
+        tokio::spawn(async move {
+            if let Err(e) = mints.resolve(&mint).await {
+                warn!("Failed to resolve mint {}: {:#}", mint, e);
+            }
+        });
+    }
+
+    /// Sizes the copy and quotes it with the pool fee. Buys are sized by the target's
+    /// profile (by default its own SOL amount) within the entry limits, its max
+    /// position and the risk limits; sells mirror the fraction of its position it sold.
+    /// Buys come with their risk reservation.
+    fn plan(&self, trade: &TradeEvent, sell_fraction: Option<f64>) -> Option<(CopyOrder, Option<BuyReservation>)> {
+        let profile = self.profile(&trade.wallet);
+        let holding = self.holdings().get(&(trade.wallet.clone(), trade.mint.clone())).copied().unwrap_or_default();
+        let (amount_in, reservation) = match trade.side {
+            Side::Buy => {
+                let target_sol = trade.sol_amount as f64 / LAMPORTS_PER_SOL;
+                let size = match profile.as_ref().and_then(|profile| profile.sizing.as_ref()) {
+                    Some(SizingConfig::Fixed { amount }) => *amount,
+                    Some(SizingConfig::Ratio { amount }) => target_sol * amount,
+                    None => target_sol,
+                };
+                let lamports = (size.clamp(self.trade_settings.min_entry, self.trade_settings.max_entry) * LAMPORTS_PER_SOL) as u64;
+                let lamports = match profile.as_ref().and_then(|profile| profile.max_position) {
+                    Some(max_position) => lamports.min(((max_position * LAMPORTS_PER_SOL) as u64).saturating_sub(holding.cost)),
+                    None => lamports,
+                };
+                if lamports == 0 {
+                    return None;
+                }
+                match self.risk.admit_buy(&trade.mint, lamports) {
+                    Ok(reservation) => (reservation.lamports(), Some(reservation)),
+                    Err(reason) => {
+                        self.block(trade, &reason);
+                        return None;
+                    }
+                }
+            }
+            Side::Sell => ((holding.tokens as f64 * sell_fraction?) as u64, None),
+        };
+        let priority_fee = profile.map(|profile| profile.priority_fee).unwrap_or(PriorityFeeTier::Medium);
+        // An order that cannot be quoted drops its reservation, releasing it
+        Some((self.order(trade, trade.side, amount_in, priority_fee)?, reservation))
+    }
+
+    /// Quotes a `side` swap of `amount_in` on the pool of `trade`, against the latest
+    /// known state of the pool.
+    fn order(&self, trade: &TradeEvent, side: Side, amount_in: u64, priority_fee: PriorityFeeTier) -> Option<CopyOrder> {
+        if amount_in == 0 {
+            return None;
+        }
+        let (Ok(pool), Ok(mint)) = (Pubkey::from_str(&trade.pool), Pubkey::from_str(&trade.mint)) else {
+            return None;
+        };
+        // The cache is at least as fresh as the target's trade, which updated it
+        let state = POOLS.get(&trade.pool).filter(|state| state.mint == trade.mint).unwrap_or_else(|| PoolState {
+            dex: trade.dex,
+            mint: trade.mint.clone(),
+            sol_reserve: trade.sol_reserve,
+            token_reserve: trade.token_reserve,
+            real_token_reserve: None,
+            slot: trade.slot,
+            signature: Some(trade.signature.clone()),
+            vaults: None,
+        });
+        if state.signature.as_deref() != Some(trade.signature.as_str()) {
+            info!("Pool {} moved since the target's trade, quoting at slot {}", trade.pool, state.slot);
+        }
+        match trade.dex {
+            Dex::Raydium => self.quote_raydium(side, pool, mint, &state, amount_in, priority_fee),
+            Dex::Pumpfun => self.quote_pumpfun(side, pool, mint, &state, amount_in, priority_fee),
+        }
+    }
+
+    /// Uses the pool's own fee once its accounts are resolved, the standard fee until then.
+    fn quote_raydium(
+        &self,
+        side: Side,
+        pool: Pubkey,
+        mint: Pubkey,
+        state: &PoolState,
+        amount_in: u64,
+        priority_fee: PriorityFeeTier,
+    ) -> Option<CopyOrder> {
+        let (reserve_in, reserve_out) = match side {
+            Side::Buy => (state.sol_reserve, state.token_reserve),
+            Side::Sell => (state.token_reserve, state.sol_reserve),
+        };
+        let fees = self.resolver.cached(&pool)
+            .map(|accounts| accounts.fees)
+            .unwrap_or_default();
+        let quote = quote_exact_in(amount_in, reserve_in, reserve_out, fees)?;
+        Some(CopyOrder {
+            dex: Dex::Raydium,
+            pool,
+            mint,
+            side,
+            amount_in: quote.amount_in,
+            expected_out: quote.amount_out,
+            limit: OrderLimit::MinimumOut(minimum_out(quote.amount_out, self.trade_settings.slippage_tolerance)),
+            fee: quote.fee,
+            priority_fee,
+        })
+    }
+
+    /// Quoted against the latest `CPILog` or account update of the bonding curve.
+    fn quote_pumpfun(
+        &self,
+        side: Side,
+        pool: Pubkey,
+        mint: Pubkey,
+        state: &PoolState,
+        amount_in: u64,
+        priority_fee: PriorityFeeTier,
+    ) -> Option<CopyOrder> {
+        let curve = BondingCurve::from(state);
+        let fee_bps = fee_basis_points();
+        let slippage_tolerance = self.trade_settings.slippage_tolerance;
+        match side {
+            Side::Buy => {
+                let quote = curve.buy_quote(amount_in, fee_bps, slippage_tolerance)?;
+                Some(CopyOrder {
+                    dex: Dex::Pumpfun,
+                    pool,
+                    mint,
+                    side: Side::Buy,
+                    amount_in: quote.sol_cost + quote.fee,
+                    expected_out: quote.token_amount,
+                    limit: OrderLimit::MaxSolCost(quote.max_sol_cost),
+                    fee: quote.fee,
+                    priority_fee,
+                })
+            }
+            Side::Sell => {
+                let quote = curve.sell_quote(amount_in, fee_bps, slippage_tolerance)?;
+                Some(CopyOrder {
+                    dex: Dex::Pumpfun,
+                    pool,
+                    mint,
+                    side: Side::Sell,
+                    amount_in: quote.token_amount,
+                    expected_out: quote.sol_output,
+                    limit: OrderLimit::MinimumOut(quote.min_sol_output),
+                    fee: quote.fee,
+                    priority_fee,
+                })
+            }
+        }
+    }
+
+    pub fn on_trade_confirmed(&self, trade: &TradeEvent) {
+        Metrics::incr(&METRICS.trades_confirmed);
+        self.copies().remove(&trade.signature);
+        info!("CONFIRMED {} at slot {}", trade.signature, trade.slot);
+    }
+
+    /// Called when the slot of a trade we acted on turns out dead or skipped: the
+    /// target's trade never really happened.
+    pub fn on_trade_orphaned(self: &Arc<Self>, trade: &TradeEvent, reason: &str) {
+        Metrics::incr(&METRICS.trades_orphaned);
+        self.positions().revert(&self.mints.received(trade));
+        error!(
+            "ALERT target trade {} at slot {} was dropped ({}): {:?} {} of {}",
+            trade.signature, trade.slot, reason, trade.side, trade.sol_amount, trade.mint
+        );
+        let mut copies = self.copies();
+        match copies.remove(&trade.signature) {
+            Some(CopyState::Landed(fill)) => {
+                drop(copies);
+                self.unwind(trade, fill);
+            }
+            // Unwound once it lands
+            Some(CopyState::InFlight) => {
+                copies.insert(trade.signature.clone(), CopyState::Orphaned(trade.clone()));
+            }
+            Some(CopyState::Orphaned(_)) | None => {}
+        }
+    }
+
+    /// Reverses our landed copy of a dropped target trade: sells the tokens a copied
+    /// buy got, as far as they are still held, or buys back with what a copied sell
+    /// received, within the risk limits.
+    fn unwind(self: &Arc<Self>, trade: &TradeEvent, fill: Fill) {
+        let priority_fee = self.profile(&trade.wallet).map(|profile| profile.priority_fee).unwrap_or(PriorityFeeTier::Medium);
+        let (side, amount_in, reservation) = match trade.side {
+            Side::Buy => {
+                let held = self.holdings().get(&(trade.wallet.clone(), trade.mint.clone())).map_or(0, |holding| holding.tokens);
+                (Side::Sell, fill.tokens.min(held), None)
+            }
+            Side::Sell => match self.risk.admit_buy(&trade.mint, fill.lamports) {
+                Ok(reservation) => (Side::Buy, reservation.lamports(), Some(reservation)),
+                Err(reason) => {
+                    self.block(trade, &format!("unwind refused: {}", reason));
+                    return;
+                }
+            },
+        };
+        let Some(order) = self.order(trade, side, amount_in, priority_fee) else {
+            warn!("Nothing to unwind for {}", trade.signature);
+            return;
+        };
+        warn!("UNWIND copy of {}: {:?} {} in for {} out on {}", trade.signature, side, order.amount_in, order.expected_out, trade.pool);
+        self.execute(&trade.wallet, order, reservation, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signature::{Keypair, Signer};
+    use tokio::time::{sleep, Duration};
+    use crate::mints::TOKEN_PROGRAM_ID;
+    use crate::rpc::{AccountData, MockRpc};
+    use crate::sender::MockSender;
+
+    const SOL: u64 = 1_000_000_000;
+
+    /// A target buy goes through the whole pipeline without the network: screened,
+    /// sized and quoted, built, signed and recorded by the mock sender, confirmed on
+    /// the mock chain, and booked in our holdings.
+    #[tokio::test]
+    async fn copies_a_target_buy_end_to_end() {
+        let mut config: Config = serde_json::from_str(include_str!("../config/default.json")).unwrap();
+        config.safety.enabled = false;
+        config.execution.confirmation.poll_ms = 0;
+        config.risk.state_dir = std::env::temp_dir().join(format!("copier-test-{}", rand::random::<u64>())).display().to_string();
+
+        let (wallet, pool, mint) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mut profile = config.wallets.targets[0].clone();
+        profile.wallet = wallet.to_string();
+        TARGETS.set(vec![profile]);
+
+        let mint_account = AccountData { owner: TOKEN_PROGRAM_ID, data: vec![0; 82] };
+        let rpc = Arc::new(MockRpc { accounts: [(mint, mint_account)].into_iter().collect(), ..MockRpc::landing() });
+        let resolver = Arc::new(PoolResolver::with_rpc(rpc.clone(), Pubkey::new_unique()));
+        let mints = Arc::new(MintResolver::with_rpc(rpc.clone()));
+        let sender = Arc::new(MockSender::default());
+        let keypair = Keypair::new();
+        let owner = keypair.pubkey();
+        let executor = Executor::with_parts(&config, keypair, rpc, sender.clone(), resolver.clone(), mints.clone()).unwrap();
+        let risk = Arc::new(RiskManager::load(&config).unwrap());
+        let positions = Arc::new(Mutex::new(PositionBook::default()));
+        let copier = Arc::new(Copier::new(&config, positions.clone(), resolver, mints, risk, Some(Arc::new(executor))));
+
+        let trade = TradeEvent {
+            dex: Dex::Pumpfun,
+            signature: "target-buy".to_owned(),
+            slot: 100,
+            wallet: wallet.to_string(),
+            pool: pool.to_string(),
+            mint: mint.to_string(),
+            side: Side::Buy,
+            sol_amount: SOL / 2,
+            token_amount: 17_000_000_000_000,
+            sol_reserve: 30 * SOL,
+            token_reserve: 1_073_000_000_000_000,
+            price_impact: 0.01,
+        };
+        copier.on_target_trade(&trade);
+
+        let key = (trade.wallet.clone(), trade.mint.clone());
+        let mut holding = None;
+        for _ in 0..200 {
+            holding = copier.holdings().get(&key).copied().filter(|holding| holding.tokens > 0);
+            if holding.is_some() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        let holding = holding.expect("the copy was not booked");
+
+        let curve = BondingCurve::from(&PoolState {
+            dex: Dex::Pumpfun,
+            mint: trade.mint.clone(),
+            sol_reserve: trade.sol_reserve,
+            token_reserve: trade.token_reserve,
+            real_token_reserve: None,
+            slot: trade.slot,
+            signature: None,
+            vaults: None,
+        });
+        let quote = curve.buy_quote(SOL / 2, fee_basis_points(), config.trade_settings.slippage_tolerance).unwrap();
+        assert_eq!(holding.tokens, quote.token_amount);
+        assert_eq!(holding.cost, quote.sol_cost + quote.fee);
+
+        let sent = sender.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].message.account_keys[0], owner);
+        assert!(sent[0].verify().is_ok());
+        let position = copier.positions().position(&trade.wallet, &trade.mint).map(|position| position.token_amount);
+        assert_eq!(position, Some(trade.token_amount));
+        std::fs::remove_dir_all(&config.risk.state_dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, Result};
+use log::warn;
+use solana_sdk::pubkey::Pubkey;
+use crate::config::{Config, FiltersConfig};
+use crate::targets::TARGETS;
+use crate::rpc::RpcClient;
+use crate::trade::{Dex, TradeEvent};
+
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+/// AMM v4 `AmmInfo` pool open time, in unix seconds
+const AMM_POOL_OPEN_TIME: usize = 224;
+const SIGNATURES_PAGE_SIZE: usize = 1000;
+/// Pages of bonding curve history walked looking for its creation
+const MAX_AGE_PAGES: usize = 5;
+
+/// Decides which target buys are worth copying, between decoding and execution.
+/// Sells are never filtered: what we hold is always mirrored out.
+pub struct CopyFilter {
+    config: FiltersConfig,
+    rpc: RpcClient,
+    /// Pool -> unix time it was created at or before, `None` if that could not be
+    /// found, so the history of a pool is walked at most once
+    created: Mutex<HashMap<String, Option<i64>>>,
+}
+
+impl CopyFilter {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.filters.clone(),
+            rpc: RpcClient::new(&config.rpc.endpoint),
+            created: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks a target buy against the mint lists and the size, liquidity and price
+    /// impact thresholds. Returns the reason to skip it, if any.
+    pub fn screen(&self, trade: &TradeEvent) -> Option<String> {
+        let target = TARGETS.profile(&trade.wallet).map(|target| target.mints);
+        let target = target.as_ref();
+        let denied = std::iter::once(&self.config.mints).chain(target)
+            .any(|lists| lists.denylist.contains(&trade.mint));
+        if denied {
+            return Some(format!("mint {} is denylisted", trade.mint));
+        }
+        let allowed = std::iter::once(&self.config.mints).chain(target)
+            .all(|lists| lists.allowlist.is_empty() || lists.allowlist.contains(&trade.mint));
+        if !allowed {
+            return Some(format!("mint {} is not allowlisted", trade.mint));
+        }
+        let target_sol = trade.sol_amount as f64 / LAMPORTS_PER_SOL;
+        if target_sol < self.config.min_target_sol {
+            return Some(format!("target trade of {:.4} SOL is below {} SOL", target_sol, self.config.min_target_sol));
+        }
+        let pool_sol = trade.sol_reserve as f64 / LAMPORTS_PER_SOL;
+        if pool_sol < self.config.min_pool_sol {
+            return Some(format!("pool holds {:.2} SOL, below {} SOL", pool_sol, self.config.min_pool_sol));
+        }
+        if trade.price_impact > self.config.max_price_impact {
+            return Some(format!(
+                "target trade moved the price {:.2}%, above {:.2}%",
+                trade.price_impact * 100.0, self.config.max_price_impact * 100.0
+            ));
+        }
+        None
+    }
+
+    /// Checks the token's age: the open time of a Raydium pool, the creation of a
+    /// Pump.fun bonding curve, cached per pool. Ages that cannot be found let the
+    /// trade through.
+    pub async fn check_age(&self, trade: &TradeEvent) -> Option<String> {
+        let max_age = self.config.max_token_age_secs?;
+        let cached = match self.created.lock() {
+            Ok(created) => created.get(&trade.pool).copied(),
+            Err(poisoned) => poisoned.into_inner().get(&trade.pool).copied(),
+        };
+        let created = match cached {
+            Some(created) => created?,
+            None => match self.find_creation(trade, max_age).await {
+                Ok(created) => {
+                    match self.created.lock() {
+                        Ok(mut cache) => cache.insert(trade.pool.clone(), created),
+                        Err(poisoned) => poisoned.into_inner().insert(trade.pool.clone(), created),
+                    };
+                    created?
+                }
+                // Not cached, the next buy of the pool tries again
+                Err(e) => {
+                    warn!("Failed to find the age of {}: {:#}", trade.mint, e);
+                    return None;
+                }
+            },
+        };
+        let age = unix_now() - created;
+        if age > max_age as i64 {
+            return Some(format!("token is {}s old, above {}s", age, max_age));
+        }
+        None
+    }
+
+    /// Unix time the pool was created at, or at least by. Pump.fun curves are walked
+    /// back until their first transaction, or until they are known to be older than
+    /// `max_age`.
+    async fn find_creation(&self, trade: &TradeEvent, max_age: u64) -> Result<Option<i64>> {
+        let pool = Pubkey::from_str(&trade.pool)?;
+        if trade.dex == Dex::Raydium {
+            let amm = self.rpc.get_account(&pool).await?
+                .ok_or_else(|| anyhow!("Pool {} not found", pool))?;
+            let open_time = amm.data.get(AMM_POOL_OPEN_TIME..AMM_POOL_OPEN_TIME + 8)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(u64::from_le_bytes)
+                .ok_or_else(|| anyhow!("{} is not an AMM v4 pool", pool))?;
+            return Ok(Some(open_time as i64));
+        }
+
+        let mut before: Option<String> = None;
+        for _ in 0..MAX_AGE_PAGES {
+            let page = self.rpc.get_signatures_for_address(&trade.pool, before.as_deref(), SIGNATURES_PAGE_SIZE).await?;
+            let Some(oldest) = page.last() else {
+                break;
+            };
+            let oldest_time = oldest.block_time;
+            if page.len() < SIGNATURES_PAGE_SIZE {
+                return Ok(oldest_time);
+            }
+            if oldest_time.is_some_and(|time| unix_now() - time > max_age as i64) {
+                return Ok(oldest_time);
+            }
+            before = Some(oldest.signature.clone());
+        }
+        Ok(None)
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs() as i64).unwrap_or_default()
+}
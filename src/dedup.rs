@@ -0,0 +1,50 @@
+use std::collections::{HashMap, VecDeque};
+use tokio::time::{Duration, Instant};
+
+pub enum Arrival {
+    First,
+    /// Already seen, with the delay since the first copy arrived
+    Duplicate(Duration),
+}
+
+/// Bounded, time-windowed set of transaction signatures. Used to process only the
+/// first copy of a transaction when several feeds deliver it.
+pub struct SignatureDedup {
+    window: Duration,
+    capacity: usize,
+    seen: HashMap<Vec<u8>, Instant>,
+    order: VecDeque<(Vec<u8>, Instant)>,
+}
+
+impl SignatureDedup {
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity,
+            seen: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn check(&mut self, signature: &[u8]) -> Arrival {
+        let now = Instant::now();
+        self.evict(now);
+
+        if let Some(first_seen) = self.seen.get(signature) {
+            return Arrival::Duplicate(now.duration_since(*first_seen));
+        }
+        self.seen.insert(signature.to_vec(), now);
+        self.order.push_back((signature.to_vec(), now));
+        Arrival::First
+    }
+
+    fn evict(&mut self, now: Instant) {
+        while let Some((signature, inserted)) = self.order.front() {
+            if now.duration_since(*inserted) <= self.window && self.order.len() < self.capacity {
+                break;
+            }
+            self.seen.remove(signature);
+            self.order.pop_front();
+        }
+    }
+}
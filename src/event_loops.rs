@@ -1,96 +1,233 @@
-use log::{info};
-use tokio::time::{sleep, Duration};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use log::{info, warn};
+use tokio::time::{interval, sleep, timeout, Duration};
 use anyhow::{Result, Context};
-use futures::StreamExt;
-use solana_sdk::signature::Signature;
+use futures::{SinkExt, StreamExt};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
 use solana_transaction_status::TransactionWithStatusMeta;
-use yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdateTransactionInfo};
+use tokio::sync::mpsc;
+use yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SlotStatus, SubscribeUpdate, SubscribeUpdateTransactionInfo};
 use yellowstone_grpc_proto::convert_from;
-use crate::config::Config;
+use yellowstone_grpc_proto::tonic::{Code, Status};
+use crate::backoff::Reconnector;
+use crate::config::{Config, FeedMode, GrpcEndpoint};
+use crate::copier::Copier;
+use crate::dedup::{Arrival, SignatureDedup};
+use crate::metrics::{Metrics, METRICS};
 use crate::parser::{decode_pumpfun_txn, decode_raydium_txn};
+use crate::pools::POOLS;
+use crate::recorder::{list_recordings, RecordingReader, UpdateRecorder};
+use crate::resume::SlotCheckpoint;
+use crate::slots::{Resolution, SlotTracker};
+use crate::targets::TARGETS;
+use crate::trade::{Dex, TradeEvent};
+use crate::undecoded::UndecodedSink;
 
-pub async fn start_monitor_loop(config: Config, selector: u8) -> Result<()> {
-    // Spawn the monitoring task
+const FAILBACK_CHECK_SECS: u64 = 5;
+const TARGETS_CHECK_SECS: u64 = 5;
+const FEED_CHANNEL_CAPACITY: usize = 4096;
+
+/// The provider refused to replay from the requested slot, e.g. one older than it
+/// keeps. Transport failures are not this, their next attempt still resumes.
+#[derive(Debug)]
+struct ResumeRejected(Status);
+
+impl fmt::Display for ResumeRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "from_slot rejected: {}", self.0)
+    }
+}
+
+impl std::error::Error for ResumeRejected {}
+
+/// An update as received from one of the gRPC feeds.
+pub struct FeedUpdate {
+    pub feed: Arc<str>,
+    pub update: SubscribeUpdate,
+}
+
+/// Starts the feeds and the processor of a monitor. Returns its slot checkpoint, if
+/// resuming is enabled, to be flushed on shutdown.
+pub async fn start_monitor_loop(
+    config: Config,
+    selector: u8,
+    undecoded: Arc<UndecodedSink>,
+    copier: Arc<Copier>,
+) -> Result<Option<Arc<SlotCheckpoint>>> {
+    let (feed_tx, feed_rx) = mpsc::channel(FEED_CHANNEL_CAPACITY);
+
+    // A replay stands in for the gRPC feeds, everything downstream is identical. The
+    // live checkpoint is left alone so replayed slots don't leak into it.
+    if config.replay.enabled {
+        tokio::spawn(run_replay(config.clone(), selector, feed_tx));
+        tokio::spawn(async move {
+            process_updates(&config, selector, &undecoded, &copier, None, feed_rx).await;
+            info!("{} processor stopped, replay is over", dex_name(selector));
+        });
+        return Ok(None);
+    }
+
+    let recorder = if config.recorder.enabled {
+        Some(Arc::new(UpdateRecorder::new(&config.recorder.dir, &dex_name(selector).to_lowercase())?))
+    } else {
+        None
+    };
+
+    let checkpoint = if config.grpc.resume.enabled {
+        std::fs::create_dir_all(&config.grpc.resume.state_dir)?;
+        let path = Path::new(&config.grpc.resume.state_dir)
+            .join(format!("checkpoint_{}.json", dex_name(selector).to_lowercase()));
+        let checkpoint = SlotCheckpoint::load(path)?;
+        if let Some(slot) = checkpoint.resume_slot() {
+            info!("{} monitor resuming from slot {}", dex_name(selector), slot);
+        }
+        let checkpoint = Arc::new(checkpoint);
+        checkpoint.start();
+        Some(checkpoint)
+    } else {
+        None
+    };
+
+    // In failover mode a single feed walks the endpoint list, in redundant mode every
+    // endpoint gets its own feed and the processor keeps whichever copy arrives first
+    let feeds = match config.grpc.mode {
+        FeedMode::Failover => vec![config.grpc.endpoints.clone()],
+        FeedMode::Redundant => config.grpc.endpoints.iter().map(|endpoint| vec![endpoint.clone()]).collect(),
+    };
+    for endpoints in feeds {
+        let reconnector = Reconnector::new(endpoints, config.grpc.reconnect.clone());
+        tokio::spawn(run_feed(config.clone(), selector, reconnector, checkpoint.clone(), recorder.clone(), feed_tx.clone()));
+    }
+
+    // Spawn the processing task
+    let processor_checkpoint = checkpoint.clone();
     tokio::spawn(async move {
-        loop {
-            match monitor_wallet(&config, selector).await {
-                Ok(_) => {
-                    match selector {
-                        0 => info!("RAYDIUM monitor loop ended unexpectedly"),
-                        1 => info!("PUMPFUN monitor loop ended unexpectedly"),
-                        _ => {
-                            info!("Not supposed to get here")
-                        }
-                    }
+        process_updates(&config, selector, &undecoded, &copier, processor_checkpoint.as_deref(), feed_rx).await;
+        info!("{} processor stopped, all feeds are gone", dex_name(selector));
+    });
+
+    Ok(checkpoint)
+}
+
+async fn run_feed(
+    config: Config,
+    selector: u8,
+    mut reconnector: Reconnector,
+    checkpoint: Option<Arc<SlotCheckpoint>>,
+    recorder: Option<Arc<UpdateRecorder>>,
+    feed_tx: mpsc::Sender<FeedUpdate>,
+) {
+    let mut resume = true;
+    loop {
+        // Replay from the last processed slot, unless the provider refused to on the
+        // previous attempt, in which case subscribe live
+        let from_slot = match &checkpoint {
+            Some(checkpoint) if resume => checkpoint.resume_slot(),
+            _ => None,
+        };
+        let result = monitor_wallet(&config, selector, &mut reconnector, from_slot, recorder.as_deref(), &feed_tx).await;
+        resume = !result.as_ref().is_err_and(|e| e.is::<ResumeRejected>());
+        if !resume {
+            warn!("{} monitor could not resume from slot {:?}, subscribing live", dex_name(selector), from_slot);
+        }
+        match result {
+            Ok(_) => {
+                // The monitor only returns cleanly to fail back to the primary endpoint
+                info!("{} monitor failing back to primary endpoint {}", dex_name(selector), reconnector.primary().endpoint);
+                reconnector.fail_back();
+                continue;
+            }
+            Err(e) => {
+                if feed_tx.is_closed() {
+                    return;
                 }
-                Err(e) => {
-                    match selector {
-                        0 => info!("RAYDIUM monitor reconnecting due to: {}", e),
-                        1 => info!("PUMPFUN monitor reconnecting due to: {}", e),
-                        _ => {
-                            info!("Not supposed to get here")
-                        }
+                match selector {
+                    0 => info!("RAYDIUM monitor reconnecting due to: {}", e),
+                    1 => info!("PUMPFUN monitor reconnecting due to: {}", e),
+                    _ => {
+                        info!("Not supposed to get here")
                     }
                 }
             }
-            
-            // Wait before attempting to reconnect
-            info!("Attempting to reconnect in 5 seconds...");
-            sleep(Duration::from_secs(5)).await;
         }
-    });
-
-    Ok(())
+        
+        // Wait before attempting to reconnect
+        let delay = reconnector.on_failure();
+        info!("Attempting to reconnect to {} in {} ms...", reconnector.endpoint().endpoint, delay.as_millis());
+        sleep(delay).await;
+    }
 }
 
-async fn monitor_wallet(config: &Config, selector: u8) -> Result<()> {
+async fn monitor_wallet(
+    config: &Config,
+    selector: u8,
+    reconnector: &mut Reconnector,
+    from_slot: Option<u64>,
+    recorder: Option<&UpdateRecorder>,
+    feed_tx: &mpsc::Sender<FeedUpdate>,
+) -> Result<()> {
     // Initialize wallet monitor
-    let (_tx, mut grpc_rx) = config.grpc_monitor(selector).await?;
+    let mut pools_version = POOLS.version();
+    let mut targets_version = TARGETS.version();
+    let (mut grpc_tx, mut grpc_rx) = config
+        .grpc_monitor(reconnector.endpoint(), selector, from_slot, watched_pool_accounts(config, selector))
+        .await?;
     match selector {
-        0 => info!("RAYDIUM monitor initialized successfully"),
-        1 => info!("PUMPFUN monitor initialized successfully"),
+        0 => info!("RAYDIUM monitor initialized successfully on {}", reconnector.endpoint().endpoint),
+        1 => info!("PUMPFUN monitor initialized successfully on {}", reconnector.endpoint().endpoint),
         _ => {
             info!("Not supposed to get here")
         }
     }
 
-    while let Some(message) = grpc_rx.next().await {
+    let feed: Arc<str> = reconnector.endpoint().endpoint.as_str().into();
+    let mut connected = false;
+    let mut failback_probe = interval(Duration::from_secs(FAILBACK_CHECK_SECS));
+    let mut pools_refresh = interval(Duration::from_secs(config.pools.refresh_secs.max(1)));
+    let mut targets_refresh = interval(Duration::from_secs(TARGETS_CHECK_SECS));
+    loop {
+        let message = tokio::select! {
+            message = grpc_rx.next() => match message {
+                Some(message) => message,
+                None => break,
+            },
+            _ = failback_probe.tick() => {
+                if reconnector.should_probe_primary() && probe_endpoint(config, reconnector.primary()).await {
+                    return Ok(());
+                }
+                continue;
+            }
+            _ = pools_refresh.tick(), if config.pools.subscribe_accounts => {
+                // A new request replaces the filters of the live subscription
+                if POOLS.version() != pools_version {
+                    pools_version = POOLS.version();
+                    let request = config.build_request_monitor_wallet(selector, None, watched_pool_accounts(config, selector))?;
+                    grpc_tx.send(request).await.map_err(|e| anyhow::anyhow!("Failed to update subscription: {}", e))?;
+                }
+                continue;
+            }
+            _ = targets_refresh.tick() => {
+                if TARGETS.version() != targets_version {
+                    targets_version = TARGETS.version();
+                    let request = config.build_request_monitor_wallet(selector, None, watched_pool_accounts(config, selector))?;
+                    grpc_tx.send(request).await.map_err(|e| anyhow::anyhow!("Failed to update subscription: {}", e))?;
+                }
+                continue;
+            }
+        };
         match message {
-            Ok(info) => {
-                match info.update_oneof {
-                    Some(UpdateOneof::Transaction(tx)) => {
-                        let slot = tx.slot;
-                        let transaction = match tx.transaction {
-                            Some(tx) => tx,
-                            None => {
-                                continue;
-                            }
-                        };
-                        let pretty_tx = create_pretty_transaction(transaction)?;
-                        match selector {
-                            0 => {
-                                info!("RAYDIUM Transaction at slot {}: {:#?}", slot, &pretty_tx);
-                                let _decoded_tx = decode_raydium_txn(&pretty_tx.tx)?;
-                                
-                            },
-                            1 => {
-                                info!("PUMPFUN Transaction at slot {}: {:#?}", slot, &pretty_tx);
-                                let _decoded_tx = decode_pumpfun_txn(&pretty_tx.tx)?;
-                                
-                            },
-                            _ => {
-                                info!("Not supposed to get here ");
-                            }
-                        }
-                    }
-                    Some(UpdateOneof::Slot(_slot)) => {
-                        // Uncomment if you want to log slots
-                        //info!("Slot: {:#?}", slot);
-                    }
-                    _ => {
-                        continue;
-                    }
+            Ok(update) => {
+                if !connected {
+                    connected = true;
+                    reconnector.on_connected();
+                }
+                if let Some(recorder) = recorder {
+                    recorder.record(&feed, &update);
                 }
+                feed_tx.send(FeedUpdate { feed: feed.clone(), update }).await
+                    .map_err(|_| anyhow::anyhow!("Processor is gone"))?;
             }
             Err(e) => {
                 match selector {
@@ -100,6 +237,10 @@ async fn monitor_wallet(config: &Config, selector: u8) -> Result<()> {
                         info!("Not supposed to get here")
                     }
                 }
+                // An error status before any data answers the subscription itself
+                if from_slot.is_some() && !connected && rejects_from_slot(&e) {
+                    return Err(ResumeRejected(e).into());
+                }
                 return Err(anyhow::anyhow!("gRPC connection error: {}", e));
             }
         }
@@ -109,6 +250,209 @@ async fn monitor_wallet(config: &Config, selector: u8) -> Result<()> {
     Err(anyhow::anyhow!("Stream ended unexpectedly"))
 }
 
+/// Feeds recorded updates to the processor, pacing them like they originally
+/// arrived divided by the configured speed (0 replays as fast as possible).
+async fn run_replay(config: Config, selector: u8, feed_tx: mpsc::Sender<FeedUpdate>) {
+    let files = match list_recordings(&config.replay.dir, &dex_name(selector).to_lowercase()) {
+        Ok(files) => files,
+        Err(e) => {
+            warn!("{} replay failed to list recordings: {:#}", dex_name(selector), e);
+            return;
+        }
+    };
+    info!("{} replaying {} recordings at {}x", dex_name(selector), files.len(), config.replay.speed);
+
+    let mut previous_us: Option<u64> = None;
+    for path in files {
+        let reader = match RecordingReader::open(&path) {
+            Ok(reader) => reader,
+            Err(e) => {
+                warn!("Skipping recording {}: {:#}", path.display(), e);
+                continue;
+            }
+        };
+        for frame in reader {
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(e) => {
+                    warn!("Recording {} is corrupted past this point: {:#}", path.display(), e);
+                    break;
+                }
+            };
+            if config.replay.speed > 0.0 {
+                if let Some(previous_us) = previous_us {
+                    let delay_us = frame.received_at_us.saturating_sub(previous_us) as f64 / config.replay.speed;
+                    sleep(Duration::from_micros(delay_us as u64)).await;
+                }
+            }
+            previous_us = Some(frame.received_at_us);
+
+            let Some(update) = frame.update else {
+                continue;
+            };
+            if feed_tx.send(FeedUpdate { feed: frame.feed.into(), update }).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Consumes the updates of every feed of a monitor. Only the first copy of each
+/// transaction is decoded, later copies just feed the per-feed arrival statistics.
+/// Transactions of the checkpointed slot that were already handled are skipped.
+async fn process_updates(
+    config: &Config,
+    selector: u8,
+    undecoded: &UndecodedSink,
+    copier: &Arc<Copier>,
+    checkpoint: Option<&SlotCheckpoint>,
+    mut feed_rx: mpsc::Receiver<FeedUpdate>,
+) {
+    let mut slots = SlotTracker::new(&config.commitment);
+    let mut dedup = SignatureDedup::new(
+        Duration::from_secs(config.grpc.dedup_window_secs),
+        config.grpc.dedup_capacity,
+    );
+    while let Some(FeedUpdate { feed, update }) = feed_rx.recv().await {
+        match update.update_oneof {
+            Some(UpdateOneof::Transaction(tx)) => {
+                let slot = tx.slot;
+                let transaction = match tx.transaction {
+                    Some(tx) => tx,
+                    None => {
+                        continue;
+                    }
+                };
+                let signature = bs58::encode(&transaction.signature).into_string();
+                if checkpoint.is_some_and(|checkpoint| checkpoint.handled(slot, &signature)) {
+                    continue;
+                }
+                match dedup.check(&transaction.signature) {
+                    Arrival::First => METRICS.record_first_arrival(&feed),
+                    Arrival::Duplicate(lag) => {
+                        METRICS.record_late_arrival(&feed, lag);
+                        continue;
+                    }
+                }
+                Metrics::incr(&METRICS.transactions_received);
+                // Decode failures are isolated to the transaction that caused them
+                let pretty_tx = match create_pretty_transaction(transaction) {
+                    Ok(pretty_tx) => pretty_tx,
+                    Err(e) => {
+                        undecoded.record(dex_name(selector), slot, &signature, &e);
+                        if let Some(checkpoint) = checkpoint {
+                            checkpoint.record_transaction(slot, &signature);
+                        }
+                        continue;
+                    }
+                };
+                let decoded = match selector {
+                    0 => {
+                        info!("RAYDIUM Transaction at slot {}: {:#?}", slot, &pretty_tx);
+                        decode_raydium_txn(&pretty_tx.tx, slot)
+                    },
+                    1 => {
+                        info!("PUMPFUN Transaction at slot {}: {:#?}", slot, &pretty_tx);
+                        decode_pumpfun_txn(&pretty_tx.tx, slot, true)
+                    },
+                    _ => {
+                        info!("Not supposed to get here ");
+                        continue;
+                    }
+                };
+                match decoded {
+                    Ok(trades) => {
+                        Metrics::incr(&METRICS.transactions_decoded);
+                        // Act on the trade right away, then keep it until its slot is
+                        // confirmed or dropped
+                        for trade in trades {
+                            copier.on_target_trade(&trade);
+                            if let Some((trade, resolution)) = slots.track(trade) {
+                                on_resolution(copier, &trade, resolution);
+                            }
+                        }
+                    }
+                    Err(e) => undecoded.record(dex_name(selector), slot, &pretty_tx.signature, &e),
+                }
+                if let Some(checkpoint) = checkpoint {
+                    checkpoint.record_transaction(slot, &pretty_tx.signature);
+                }
+            }
+            Some(UpdateOneof::Slot(slot)) => {
+                // Uncomment if you want to log slots
+                //info!("Slot: {:#?}", slot);
+                for (trade, resolution) in slots.on_slot(&slot) {
+                    on_resolution(copier, &trade, resolution);
+                }
+                if let Some(checkpoint) = checkpoint {
+                    if matches!(slot.status(), SlotStatus::SlotProcessed | SlotStatus::SlotConfirmed | SlotStatus::SlotFinalized) {
+                        checkpoint.record_slot(slot.slot);
+                    }
+                }
+            }
+            Some(UpdateOneof::Account(account)) => {
+                let Some(info) = account.account else {
+                    continue;
+                };
+                if let Ok(pubkey) = Pubkey::try_from(info.pubkey.as_slice()) {
+                    POOLS.apply_account(&pubkey, &info.data, account.slot);
+                }
+            }
+            _ => {
+                continue;
+            }
+        }
+    }
+}
+
+/// Bonding curves or vaults of the most recently traded pools of this monitor's DEX,
+/// if account subscriptions are enabled.
+fn watched_pool_accounts(config: &Config, selector: u8) -> Vec<String> {
+    if !config.pools.subscribe_accounts {
+        return vec![];
+    }
+    let dex = match selector {
+        0 => Dex::Raydium,
+        _ => Dex::Pumpfun,
+    };
+    POOLS.watched_accounts(dex, config.pools.max_watched)
+}
+
+fn on_resolution(copier: &Arc<Copier>, trade: &TradeEvent, resolution: Resolution) {
+    match resolution {
+        Resolution::Confirmed => copier.on_trade_confirmed(trade),
+        Resolution::Orphaned(reason) => copier.on_trade_orphaned(trade, &reason),
+    }
+}
+
+/// Whether a subscription was refused for its request, rather than lost to the
+/// connection (`Unavailable`, `Unknown`, `Internal`, `DeadlineExceeded`...).
+fn rejects_from_slot(status: &Status) -> bool {
+    matches!(status.code(), Code::InvalidArgument | Code::OutOfRange | Code::FailedPrecondition | Code::NotFound)
+}
+
+/// Returns true if a gRPC connection to `endpoint` can be established.
+async fn probe_endpoint(config: &Config, endpoint: &GrpcEndpoint) -> bool {
+    match timeout(Duration::from_secs(FAILBACK_CHECK_SECS), config.connect_grpc(endpoint)).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            info!("Primary endpoint {} still unavailable: {}", endpoint.endpoint, e);
+            false
+        }
+        Err(_) => {
+            info!("Primary endpoint {} probe timed out", endpoint.endpoint);
+            false
+        }
+    }
+}
+
+fn dex_name(selector: u8) -> &'static str {
+    match selector {
+        0 => "RAYDIUM",
+        1 => "PUMPFUN",
+        _ => "UNKNOWN",
+    }
+}
 
 fn create_pretty_transaction(tx: SubscribeUpdateTransactionInfo) -> Result<PrettyTransaction> {
     Ok(PrettyTransaction {
@@ -127,3 +471,21 @@ pub struct PrettyTransaction {
     tx: TransactionWithStatusMeta
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_refused_subscriptions_give_up_resuming() {
+        assert!(rejects_from_slot(&Status::invalid_argument("broadcast from 100 is not available")));
+        assert!(rejects_from_slot(&Status::out_of_range("slot too old")));
+        assert!(!rejects_from_slot(&Status::unavailable("dns error")));
+        assert!(!rejects_from_slot(&Status::unknown("connection reset")));
+        assert!(!rejects_from_slot(&Status::deadline_exceeded("timeout")));
+
+        let rejected: anyhow::Error = ResumeRejected(Status::invalid_argument("too old")).into();
+        assert!(rejected.is::<ResumeRejected>());
+        assert!(!anyhow::anyhow!("gRPC connection error").is::<ResumeRejected>());
+    }
+}
@@ -0,0 +1,397 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    system_instruction, system_program, sysvar,
+    transaction::Transaction,
+};
+use solana_transaction_status::{TransactionTokenBalance, TransactionWithStatusMeta};
+use tokio::time::{sleep, Duration};
+use crate::chain_cache::ChainCache;
+use crate::config::{Config, ConfirmationConfig, PriorityFees, SenderConfig};
+use crate::confirmation::{ConfirmationTracker, Fill, Outcome, Tracked};
+use crate::copier::{CopyOrder, OrderLimit};
+use crate::keys::load_copier_keypair;
+use crate::mints::{MintResolver, TOKEN_PROGRAM_ID};
+use crate::resolver::PoolResolver;
+use crate::rpc::{ChainRpc, MockRpc, RpcClient};
+use crate::sender::{sender_from_config, TxSender};
+use crate::trade::{Dex, Side};
+
+const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey = pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+const WSOL_MINT: Pubkey = pubkey!("So11111111111111111111111111111111111111112");
+
+const PUMPFUN_GLOBAL: Pubkey = pubkey!("4wTV1YmiEkRvAtNtsSGPtUrqRYQMe5SKy2uB4Jjaxnjf");
+const PUMPFUN_FEE_RECIPIENT: Pubkey = pubkey!("CebN5WGQ4jvEPvsVU4EoHEpgzq1VV7AbicfhtW4xC9iM");
+const PUMPFUN_EVENT_AUTHORITY: Pubkey = pubkey!("Ce6TQqeHC9p8KetsN6JsjHK7UiZk7nT1FJjtuqN4Wrpc");
+const PUMPFUN_BUY: [u8; 8] = [102, 6, 61, 18, 1, 218, 235, 234];
+const PUMPFUN_SELL: [u8; 8] = [51, 230, 133, 164, 1, 127, 131, 173];
+
+/// Polls for a landed transaction before giving up on reading its fill
+const FILL_FETCH_ATTEMPTS: usize = 5;
+
+/// AMM v4 `SwapBaseIn` opcode
+const RAYDIUM_SWAP_BASE_IN: u8 = 9;
+/// SPL Token `CloseAccount` and `SyncNative` opcodes
+const TOKEN_CLOSE_ACCOUNT: u8 = 9;
+const TOKEN_SYNC_NATIVE: u8 = 17;
+/// Associated Token Account `CreateIdempotent` opcode
+const ATA_CREATE_IDEMPOTENT: u8 = 1;
+
+/// Turns copy orders into the instructions of a transaction: the swap, plus the
+/// token accounts it needs, created idempotently. Raydium swaps SOL through the
+/// owner's WSOL account, wrapped before a buy and closed after the swap unless
+/// `persistent_wsol` keeps it open between copies. Token accounts of Token-2022
+/// mints are derived and created under Token-2022.
+pub struct Executor {
+    keypair: Keypair,
+    owner: Pubkey,
+    resolver: Arc<PoolResolver>,
+    mints: Arc<MintResolver>,
+    rpc: Arc<dyn ChainRpc>,
+    chain: Arc<ChainCache>,
+    sender: Arc<dyn TxSender>,
+    tracker: ConfirmationTracker,
+    pumpfun_program: Pubkey,
+    persistent_wsol: bool,
+    compute_unit_limit: u32,
+    priority_fees: PriorityFees,
+    confirmation: ConfirmationConfig,
+}
+
+impl Executor {
+    /// `None` when no copier key is configured, or when replaying recordings, in which
+    /// case orders are only logged.
+    pub fn from_config(config: &Config, resolver: Arc<PoolResolver>, mints: Arc<MintResolver>) -> Result<Option<Self>> {
+        // Replayed trades are history, copying them would trade on the live chain
+        if config.replay.enabled {
+            info!("Replaying recordings, copy orders are only logged");
+            return Ok(None);
+        }
+        let Some(keypair) = load_copier_keypair(&config.wallets.copier)? else {
+            return Ok(None);
+        };
+        // What the mock sender records never reaches the chain, so it is followed on a
+        // mock one instead of polling RPC until its blockhash expires
+        let rpc: Arc<dyn ChainRpc> = match config.execution.sender {
+            SenderConfig::Mock => Arc::new(MockRpc::landing()),
+            _ => Arc::new(RpcClient::new(&config.rpc.endpoint)),
+        };
+        Self::with_parts(config, keypair, rpc, sender_from_config(config)?, resolver, mints).map(Some)
+    }
+
+    /// Builds the executor over the given chain and sender, and starts its chain cache.
+    pub fn with_parts(
+        config: &Config,
+        keypair: Keypair,
+        rpc: Arc<dyn ChainRpc>,
+        sender: Arc<dyn TxSender>,
+        resolver: Arc<PoolResolver>,
+        mints: Arc<MintResolver>,
+    ) -> Result<Self> {
+        let chain = Arc::new(ChainCache::with_rpc(config.execution.chain_cache.clone(), rpc.clone()));
+        chain.start();
+        Ok(Self {
+            owner: keypair.pubkey(),
+            keypair,
+            resolver,
+            mints,
+            tracker: ConfirmationTracker::with_rpc(rpc.clone(), Duration::from_millis(config.execution.confirmation.poll_ms)),
+            rpc,
+            chain,
+            sender,
+            pumpfun_program: Pubkey::from_str(&config.dex.pumpfun.program_id)?,
+            persistent_wsol: config.execution.persistent_wsol,
+            compute_unit_limit: config.execution.compute_unit_limit,
+            priority_fees: config.execution.priority_fees.clone(),
+            confirmation: config.execution.confirmation.clone(),
+        })
+    }
+
+    /// Starts following the prioritization fees of `pool`, ahead of copying a trade on it.
+    pub fn watch_fees(&self, pool: &str) {
+        if let Ok(pool) = Pubkey::from_str(pool) {
+            self.chain.watch(pool);
+        }
+    }
+
+    /// Builds, signs and sends `order`, and follows it to its final state.
+    pub async fn execute(&self, order: &CopyOrder) -> Outcome {
+        let instructions = match self.instructions(order).await {
+            Ok(instructions) => instructions,
+            Err(e) => return Outcome::Failed { signature: None, error: format!("{:#}", e) },
+        };
+        match self.submit(order, &instructions).await {
+            Ok(outcome) => outcome,
+            Err(e) => Outcome::Failed { signature: None, error: format!("{:#}", e) },
+        }
+    }
+
+    /// Sends the transaction until it lands or fails. An attempt is only resent once
+    /// its blockhash has expired, so it can no longer land alongside the next one.
+    /// Each resend gets a newer blockhash and a bumped compute unit price.
+    async fn submit(&self, order: &CopyOrder, instructions: &[Instruction]) -> Result<Outcome> {
+        let fees = &self.priority_fees;
+        self.chain.watch(order.pool);
+        let mut price = self.chain.fee_percentile(&order.pool, fees.percentile(order.priority_fee))
+            .unwrap_or(fees.min_price)
+            .clamp(fees.min_price, fees.max_price);
+        let attempts = self.confirmation.max_resends + 1;
+        let mut expired_height = 0;
+        for attempt in 1..=attempts {
+            let (blockhash, last_valid_block_height) = match self.chain.blockhash() {
+                Some(cached) if cached.1 > expired_height => cached,
+                _ => self.rpc.get_latest_blockhash().await?,
+            };
+            let transaction = self.sign(instructions, price, blockhash);
+            let signature = self.sender.send(&transaction).await?;
+            info!(
+                "SENT {:?} of {} as {} (attempt {}/{}, {} micro-lamports per CU)",
+                order.side, order.mint, signature, attempt, attempts, price
+            );
+            match self.tracker.track(&signature, last_valid_block_height).await {
+                Tracked::Landed(slot) => {
+                    let fill = match self.fill(order, &signature).await {
+                        Ok(fill) => Some(fill),
+                        Err(e) => {
+                            warn!("Failed to read the fill of {}: {:#}", signature, e);
+                            None
+                        }
+                    };
+                    return Ok(Outcome::Landed { signature, slot, fill });
+                }
+                Tracked::Failed(error) => return Ok(Outcome::Failed { signature: Some(signature), error }),
+                Tracked::Expired => {
+                    warn!("Blockhash of {} expired before it landed", signature);
+                    expired_height = last_valid_block_height;
+                    price = ((price as f64 * self.confirmation.fee_bump) as u64).min(fees.max_price);
+                }
+            }
+        }
+        Ok(Outcome::Expired { attempts })
+    }
+
+    /// Reads what the landed transaction moved from its balances: the mint in our
+    /// token accounts, SOL in our wallet and WSOL account. A confirmed transaction can
+    /// take a few polls to be served.
+    async fn fill(&self, order: &CopyOrder, signature: &Signature) -> Result<Fill> {
+        let mut transaction = None;
+        for _ in 0..FILL_FETCH_ATTEMPTS {
+            transaction = self.rpc.get_transaction(&signature.to_string()).await?;
+            if transaction.is_some() {
+                break;
+            }
+            sleep(Duration::from_millis(self.confirmation.poll_ms)).await;
+        }
+        let Some((_, TransactionWithStatusMeta::Complete(transaction))) = transaction else {
+            return Err(anyhow!("Transaction {} not found", signature));
+        };
+        let meta = &transaction.meta;
+        let index = transaction.transaction.message.static_account_keys().iter()
+            .position(|key| *key == self.owner)
+            .ok_or_else(|| anyhow!("{} is not in transaction {}", self.owner, signature))?;
+        let lamports = *meta.post_balances.get(index).unwrap_or(&0) as i128 - *meta.pre_balances.get(index).unwrap_or(&0) as i128;
+        let owner = self.owner.to_string();
+        let held = |balances: &Option<Vec<TransactionTokenBalance>>, mint: &Pubkey| -> i128 {
+            let mint = mint.to_string();
+            balances.iter().flatten()
+                .filter(|balance| balance.owner == owner && balance.mint == mint)
+                .filter_map(|balance| balance.ui_token_amount.amount.parse::<u64>().ok())
+                .map(|amount| amount as i128)
+                .sum()
+        };
+        let delta = |mint: &Pubkey| held(&meta.post_token_balances, mint) - held(&meta.pre_token_balances, mint);
+        let (tokens, lamports) = (delta(&order.mint), lamports + delta(&WSOL_MINT));
+        Ok(match order.side {
+            Side::Buy => Fill { tokens: tokens.max(0) as u64, lamports: (-lamports).max(0) as u64 },
+            Side::Sell => Fill { tokens: (-tokens).max(0) as u64, lamports: lamports.max(0) as u64 },
+        })
+    }
+
+    /// Signs the swap instructions behind the compute budget ones, followed by the
+    /// sender's own (a bundle tip).
+    fn sign(&self, instructions: &[Instruction], compute_unit_price: u64, blockhash: Hash) -> Transaction {
+        let mut all = vec![
+            ComputeBudgetInstruction::set_compute_unit_limit(self.compute_unit_limit),
+            ComputeBudgetInstruction::set_compute_unit_price(compute_unit_price),
+        ];
+        all.extend_from_slice(instructions);
+        all.extend(self.sender.extra_instructions(&self.owner));
+        Transaction::new_signed_with_payer(&all, Some(&self.owner), &[&self.keypair], blockhash)
+    }
+
+    /// Builds the instructions of `order`, in execution order.
+    async fn instructions(&self, order: &CopyOrder) -> Result<Vec<Instruction>> {
+        let token_program = self.mints.resolve(&order.mint).await?.token_program;
+        match order.dex {
+            Dex::Raydium if token_program != TOKEN_PROGRAM_ID => {
+                Err(anyhow!("AMM v4 pools do not support Token-2022 mint {}", order.mint))
+            }
+            Dex::Raydium => self.raydium_instructions(order).await,
+            Dex::Pumpfun => self.pumpfun_instructions(order, &token_program),
+        }
+    }
+
+    async fn raydium_instructions(&self, order: &CopyOrder) -> Result<Vec<Instruction>> {
+        let accounts = self.resolver.resolve(&order.pool).await?;
+        if ![accounts.coin_mint, accounts.pc_mint].contains(&WSOL_MINT)
+            || ![accounts.coin_mint, accounts.pc_mint].contains(&order.mint)
+        {
+            return Err(anyhow!("Pool {} does not trade {} against SOL", order.pool, order.mint));
+        }
+        let OrderLimit::MinimumOut(minimum_out) = order.limit else {
+            return Err(anyhow!("Raydium swaps are bounded by their minimum output"));
+        };
+
+        let wsol_account = associated_token_address(&self.owner, &WSOL_MINT, &TOKEN_PROGRAM_ID);
+        let token_account = associated_token_address(&self.owner, &order.mint, &TOKEN_PROGRAM_ID);
+        let (source, destination) = match order.side {
+            Side::Buy => (wsol_account, token_account),
+            Side::Sell => (token_account, wsol_account),
+        };
+
+        let mut instructions = vec![create_associated_token_account_idempotent(&self.owner, &WSOL_MINT, &TOKEN_PROGRAM_ID)];
+        if order.side == Side::Buy {
+            instructions.push(system_instruction::transfer(&self.owner, &wsol_account, order.amount_in));
+            instructions.push(sync_native(&wsol_account));
+            instructions.push(create_associated_token_account_idempotent(&self.owner, &order.mint, &TOKEN_PROGRAM_ID));
+        }
+
+        let mut data = vec![RAYDIUM_SWAP_BASE_IN];
+        data.extend_from_slice(&order.amount_in.to_le_bytes());
+        data.extend_from_slice(&minimum_out.to_le_bytes());
+        instructions.push(Instruction {
+            program_id: accounts.amm_program,
+            accounts: accounts.swap_accounts(&source, &destination, &self.owner),
+            data,
+        });
+
+        if !self.persistent_wsol {
+            instructions.push(close_account(&wsol_account, &self.owner, &self.owner));
+        }
+        Ok(instructions)
+    }
+
+    fn pumpfun_instructions(&self, order: &CopyOrder, token_program: &Pubkey) -> Result<Vec<Instruction>> {
+        let associated_bonding_curve = associated_token_address(&order.pool, &order.mint, token_program);
+        let user_token_account = associated_token_address(&self.owner, &order.mint, token_program);
+        let mut instructions = vec![];
+
+        let (discriminator, amount, limit, accounts) = match (order.side, order.limit) {
+            (Side::Buy, OrderLimit::MaxSolCost(max_sol_cost)) => {
+                instructions.push(create_associated_token_account_idempotent(&self.owner, &order.mint, token_program));
+                let accounts = vec![
+                    AccountMeta::new_readonly(PUMPFUN_GLOBAL, false),
+                    AccountMeta::new(PUMPFUN_FEE_RECIPIENT, false),
+                    AccountMeta::new_readonly(order.mint, false),
+                    AccountMeta::new(order.pool, false),
+                    AccountMeta::new(associated_bonding_curve, false),
+                    AccountMeta::new(user_token_account, false),
+                    AccountMeta::new(self.owner, true),
+                    AccountMeta::new_readonly(system_program::id(), false),
+                    AccountMeta::new_readonly(*token_program, false),
+                    AccountMeta::new_readonly(sysvar::rent::id(), false),
+                    AccountMeta::new_readonly(PUMPFUN_EVENT_AUTHORITY, false),
+                    AccountMeta::new_readonly(self.pumpfun_program, false),
+                ];
+                (PUMPFUN_BUY, order.expected_out, max_sol_cost, accounts)
+            }
+            (Side::Sell, OrderLimit::MinimumOut(min_sol_output)) => {
+                let accounts = vec![
+                    AccountMeta::new_readonly(PUMPFUN_GLOBAL, false),
+                    AccountMeta::new(PUMPFUN_FEE_RECIPIENT, false),
+                    AccountMeta::new_readonly(order.mint, false),
+                    AccountMeta::new(order.pool, false),
+                    AccountMeta::new(associated_bonding_curve, false),
+                    AccountMeta::new(user_token_account, false),
+                    AccountMeta::new(self.owner, true),
+                    AccountMeta::new_readonly(system_program::id(), false),
+                    AccountMeta::new_readonly(ASSOCIATED_TOKEN_PROGRAM_ID, false),
+                    AccountMeta::new_readonly(*token_program, false),
+                    AccountMeta::new_readonly(PUMPFUN_EVENT_AUTHORITY, false),
+                    AccountMeta::new_readonly(self.pumpfun_program, false),
+                ];
+                (PUMPFUN_SELL, order.amount_in, min_sol_output, accounts)
+            }
+            _ => return Err(anyhow!("Invalid Pump.fun order limit {:?}", order.limit)),
+        };
+
+        let mut data = discriminator.to_vec();
+        data.extend_from_slice(&amount.to_le_bytes());
+        data.extend_from_slice(&limit.to_le_bytes());
+        instructions.push(Instruction { program_id: self.pumpfun_program, accounts, data });
+        Ok(instructions)
+    }
+}
+
+pub fn associated_token_address(owner: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[owner.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    ).0
+}
+
+/// Creates `owner`'s associated token account for `mint`, and succeeds if it exists.
+/// The owner pays for it.
+fn create_associated_token_account_idempotent(owner: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: ASSOCIATED_TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*owner, true),
+            AccountMeta::new(associated_token_address(owner, mint, token_program), false),
+            AccountMeta::new_readonly(*owner, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+        data: vec![ATA_CREATE_IDEMPOTENT],
+    }
+}
+
+/// Syncs a WSOL account's token balance with the lamports sent to it.
+fn sync_native(account: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: TOKEN_PROGRAM_ID,
+        accounts: vec![AccountMeta::new(*account, false)],
+        data: vec![TOKEN_SYNC_NATIVE],
+    }
+}
+
+/// Closes a token account; for WSOL this unwraps its whole balance to `destination`.
+fn close_account(account: &Pubkey, destination: &Pubkey, owner: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*account, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(*owner, true),
+        ],
+        data: vec![TOKEN_CLOSE_ACCOUNT],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_never_builds_a_sender() {
+        let mut config: Config = serde_json::from_str(include_str!("../config/default.json")).unwrap();
+        let var = "COPYTRADE_TEST_REPLAY_KEY";
+        std::env::set_var(var, Keypair::new().to_base58_string());
+        config.wallets.copier.key_env = Some(var.to_owned());
+        config.replay.enabled = true;
+        assert!(load_copier_keypair(&config.wallets.copier).unwrap().is_some());
+
+        let resolver = Arc::new(PoolResolver::new(&config).unwrap());
+        let mints = Arc::new(MintResolver::new(&config));
+        assert!(Executor::from_config(&config, resolver, mints).unwrap().is_none());
+    }
+}
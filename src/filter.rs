@@ -1,9 +1,16 @@
-use solana_transaction_status::TransactionWithStatusMeta;
+use solana_transaction_status::{TransactionWithStatusMeta, TransactionTokenBalance};
+use solana_sdk::{pubkey, pubkey::Pubkey, instruction::AccountMeta};
 use anyhow::Result;
+use crate::parser::FlattenedInstruction;
+use crate::pools::PoolState;
+use crate::quote::raydium::{quote_exact_in, quote_exact_out, RaydiumFees};
+use crate::trade::{Dex, Side, TradeEvent};
 use crate::utils::pumpfun_parser::{PumpfunInstruction, CPILog};
 
-const RD_AUTHORITY : &'static str = "5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1";
-const WSOL : &'static str = "So11111111111111111111111111111111111111112";
+const RD_AUTHORITY : &str = "5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1";
+const WSOL : &str = "So11111111111111111111111111111111111111112";
+const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP7VEwdNRMdzXdMg8oQL7BYbDEE4X2U");
 
 #[derive(Debug)]
 pub enum RaydiumType {
@@ -16,25 +23,272 @@ pub enum RaydiumType {
 
 #[derive(Debug)]
 pub struct TradeSize {
+    /// Owner of the swapped token accounts, who may differ from the fee payer
+    owner: Pubkey,
+    pool: Pubkey,
+    mint: String,
     reserve_in: u64,
     reserve_out: u64,
     amount_in: u64,
     amount_out : u64,
     price_impact: f64
 }
+
 #[derive(Debug)]
 pub struct LiquiditySize {
     sol_reserve : u64,
     token_reserve : u64
 }
 
+/// A single Raydium AMM v4 swap, reconstructed from the swap instruction and the two
+/// token transfers it invoked (user -> pool vault, pool vault -> user).
+#[derive(Debug, Clone)]
+pub struct SwapLeg {
+    pub pool: Pubkey,
+    /// User owner account of the swap, which signs for the user token accounts
+    pub owner: Pubkey,
+    pub mint_in: String,
+    pub mint_out: String,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub reserve_in: u64,
+    pub reserve_out: u64,
+    /// `SwapBaseOut`, which fixes the output and charges whatever input it takes
+    pub exact_out: bool,
+    pub vault_in: Pubkey,
+    pub vault_out: Pubkey,
+}
+
+/// A decoded SPL Token / Token-2022 transfer. `amount` is what left the source, before
+/// any Token-2022 transfer fee withheld from the destination.
+struct TokenTransfer {
+    source: Pubkey,
+    destination: Pubkey,
+    amount: u64,
+}
+
+impl TokenTransfer {
+    fn parse(ix: &FlattenedInstruction) -> Option<Self> {
+        let ix = &ix.instruction;
+        if ix.program_id != TOKEN_PROGRAM_ID && ix.program_id != TOKEN_2022_PROGRAM_ID {
+            return None;
+        }
+        let u64_at = |offset: usize| Some(u64::from_le_bytes(ix.data.get(offset..offset + 8)?.try_into().ok()?));
+        let (destination, amount) = match (ix.data.first()?, ix.data.get(1)) {
+            // Transfer: source, destination, authority
+            (3, _) => (ix.accounts.get(1)?.pubkey, u64_at(1)?),
+            // TransferChecked: source, mint, destination, authority
+            (12, _) => (ix.accounts.get(2)?.pubkey, u64_at(1)?),
+            // Token-2022 TransferCheckedWithFee: amount, decimals, fee; TransferChecked accounts
+            (26, Some(1)) if ix.program_id == TOKEN_2022_PROGRAM_ID => (ix.accounts.get(2)?.pubkey, u64_at(2)?),
+            _ => return None,
+        };
+        Some(Self { source: ix.accounts.first()?.pubkey, destination, amount })
+    }
+}
+
+impl SwapLeg {
+    /// Builds a leg from a `SwapBaseIn`/`SwapBaseOut` instruction's accounts and the
+    /// instructions it invoked. Mints and post-trade reserves are read from the token
+    /// balances of the pool vaults.
+    pub fn from_swap(
+        tx: &TransactionWithStatusMeta,
+        account_keys: &[Pubkey],
+        accounts: &[AccountMeta],
+        children: &[FlattenedInstruction],
+        exact_out: bool,
+    ) -> Result<Self> {
+        // The swap takes 17 accounts, or 18 when the optional target orders account is
+        // present; the user source, destination and owner are always the last three.
+        if accounts.len() < 17 {
+            return Err(anyhow::anyhow!("Invalid swap accounts length {}", accounts.len()));
+        }
+        let pool = accounts[1].pubkey;
+        let owner = accounts[accounts.len() - 1].pubkey;
+        let user_source = accounts[accounts.len() - 3].pubkey;
+        let user_destination = accounts[accounts.len() - 2].pubkey;
+
+        let transfers: Vec<TokenTransfer> = children.iter().filter_map(TokenTransfer::parse).collect();
+        let transfer_in = transfers.iter().find(|t| t.source == user_source)
+            .ok_or_else(|| anyhow::anyhow!("Swap input transfer not found"))?;
+        let transfer_out = transfers.iter().find(|t| t.destination == user_destination)
+            .ok_or_else(|| anyhow::anyhow!("Swap output transfer not found"))?;
+
+        let post_balances = match tx {
+            TransactionWithStatusMeta::Complete(tx_inner) => tx_inner.meta.post_token_balances.as_deref(),
+            TransactionWithStatusMeta::MissingMetadata(_) => return Err(anyhow::anyhow!("MissingMetadata")),
+        }.ok_or_else(|| anyhow::anyhow!("Post balances not found"))?;
+
+        let vault_balance = |vault: &Pubkey| -> Result<&TransactionTokenBalance> {
+            post_balances.iter()
+                .find(|balance| account_keys.get(balance.account_index as usize) == Some(vault))
+                .ok_or_else(|| anyhow::anyhow!("Vault balance not found for {}", vault))
+        };
+        let vault_in = vault_balance(&transfer_in.destination)?;
+        let vault_out = vault_balance(&transfer_out.source)?;
+
+        Ok(SwapLeg {
+            pool,
+            owner,
+            mint_in: vault_in.mint.clone(),
+            mint_out: vault_out.mint.clone(),
+            amount_in: transfer_in.amount,
+            amount_out: transfer_out.amount,
+            reserve_in: parse_amount(vault_in)?,
+            reserve_out: parse_amount(vault_out)?,
+            exact_out,
+            vault_in: transfer_in.destination,
+            vault_out: transfer_out.source,
+        })
+    }
+
+    /// State of the pool after the swap, for SOL pools only.
+    pub fn pool_state(&self, slot: u64, signature: &str) -> Option<PoolState> {
+        let (mint, sol_reserve, token_reserve, vaults) = if self.mint_in == WSOL {
+            (&self.mint_out, self.reserve_in, self.reserve_out, (self.vault_in, self.vault_out))
+        } else if self.mint_out == WSOL {
+            (&self.mint_in, self.reserve_out, self.reserve_in, (self.vault_out, self.vault_in))
+        } else {
+            return None;
+        };
+        Some(PoolState {
+            dex: Dex::Raydium,
+            mint: mint.clone(),
+            sol_reserve,
+            token_reserve,
+            real_token_reserve: None,
+            slot,
+            signature: Some(signature.to_owned()),
+            vaults: Some(vaults),
+        })
+    }
+
+    /// Price impact of the swap on the pool it hit, quoted against the reserves
+    /// before the swap with the standard pool fee.
+    fn price_impact(&self) -> f64 {
+        let reserve_in = self.reserve_in.saturating_sub(self.amount_in);
+        let reserve_out = self.reserve_out.saturating_add(self.amount_out);
+        let quote = match self.exact_out {
+            false => quote_exact_in(self.amount_in, reserve_in, reserve_out, RaydiumFees::default()),
+            true => quote_exact_out(self.amount_out, reserve_in, reserve_out, RaydiumFees::default()),
+        };
+        quote.map(|quote| quote.price_impact).unwrap_or(0.0)
+    }
+}
+
+fn parse_amount(balance: &TransactionTokenBalance) -> Result<u64> {
+    balance.ui_token_amount.amount.parse::<u64>()
+        .map_err(|_| anyhow::anyhow!("Invalid amount format"))
+}
+
 impl RaydiumType {
-    pub fn get_type(tx : &TransactionWithStatusMeta)-> Result<Self>{
+    /// Classifies every swap leg of a transaction. Consecutive legs that chain into
+    /// each other (the output mint of one is the input mint of the next) are collapsed
+    /// into a single net trade, except through SOL: a route A -> SOL -> B is a sell of
+    /// A and a buy of B. A `None` leg could not be reconstructed, and the routes on
+    /// either side of it may be missing a hop, so they are left `Unknown`.
+    /// Transactions without swaps fall back to the vault balance heuristic, which
+    /// detects liquidity changes.
+    pub fn get_types(tx : &TransactionWithStatusMeta, legs: &[Option<SwapLeg>]) -> Result<Vec<Self>> {
+        if legs.is_empty() {
+            return Ok(vec![Self::from_balances(tx)?]);
+        }
+
+        // Each route with whether it borders a gap
+        let mut routes: Vec<(Vec<&SwapLeg>, bool)> = vec![];
+        let mut after_gap = false;
+        for leg in legs {
+            let Some(leg) = leg else {
+                if let Some((_, incomplete)) = routes.last_mut() {
+                    *incomplete = true;
+                }
+                after_gap = true;
+                continue;
+            };
+            match routes.last_mut() {
+                Some((route, _)) if !after_gap && route.last().is_some_and(|prev| prev.mint_out == leg.mint_in && prev.mint_out != WSOL) => {
+                    route.push(leg)
+                }
+                _ => routes.push((vec![leg], after_gap)),
+            }
+            after_gap = false;
+        }
+
+        Ok(routes.iter().map(|(route, incomplete)| match incomplete {
+            true => RaydiumType::Unknown,
+            false => Self::from_route(route),
+        }).collect())
+    }
+
+    /// Normalizes a SOL trade into a `TradeEvent`, attributed to the owner of the
+    /// swap. Liquidity changes are not trades that can be mirrored and yield `None`.
+    pub fn trade_event(&self, signature: &str, slot: u64) -> Option<TradeEvent> {
+        let (side, size) = match self {
+            RaydiumType::Buy(size) => (Side::Buy, size),
+            RaydiumType::Sell(size) => (Side::Sell, size),
+            _ => return None,
+        };
+        let (sol_amount, token_amount, sol_reserve, token_reserve) = match side {
+            Side::Buy => (size.amount_in, size.amount_out, size.reserve_in, size.reserve_out),
+            Side::Sell => (size.amount_out, size.amount_in, size.reserve_out, size.reserve_in),
+        };
+        Some(TradeEvent {
+            dex: Dex::Raydium,
+            signature: signature.to_owned(),
+            slot,
+            wallet: size.owner.to_string(),
+            pool: size.pool.to_string(),
+            mint: size.mint.clone(),
+            side,
+            sol_amount,
+            token_amount,
+            sol_reserve,
+            token_reserve,
+            price_impact: size.price_impact,
+        })
+    }
+
+    fn from_route(route: &[&SwapLeg]) -> Self {
+        let (first, last) = match (route.first(), route.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return RaydiumType::Unknown,
+        };
+        if first.mint_in == WSOL && last.mint_out != WSOL {
+            let price_impact = last.price_impact();
+            RaydiumType::Buy(TradeSize{
+                owner: first.owner,
+                pool: last.pool,
+                mint: last.mint_out.clone(),
+                reserve_in: last.reserve_in,
+                reserve_out: last.reserve_out,
+                amount_in: first.amount_in,
+                amount_out: last.amount_out,
+                price_impact
+            })
+        } else if first.mint_in != WSOL && last.mint_out == WSOL {
+            let price_impact = first.price_impact();
+            RaydiumType::Sell(TradeSize{
+                owner: first.owner,
+                pool: first.pool,
+                mint: first.mint_in.clone(),
+                reserve_in: first.reserve_in,
+                reserve_out: first.reserve_out,
+                amount_in: first.amount_in,
+                amount_out: last.amount_out,
+                price_impact
+            })
+        } else {
+            // Token-to-token routes that never touch SOL, and round trips
+            RaydiumType::Unknown
+        }
+    }
+
+    fn from_balances(tx : &TransactionWithStatusMeta)-> Result<Self>{
         let meta = match tx.clone() {
             TransactionWithStatusMeta::Complete(tx_inner) => {
                 tx_inner.meta
             }
-            TransactionWithStatusMeta::MissingMetadata(tx_inner) => {
+            TransactionWithStatusMeta::MissingMetadata(_) => {
                 return Err(anyhow::anyhow!("MissingMetadata"));
             }
         };
@@ -52,7 +306,7 @@ impl RaydiumType {
         let (mut post_sol_balances, mut pre_sol_balances) = (0, 0);
         let (mut post_token_balances, mut pre_token_balances) = (0, 0);
         for account in &pre_balances{
-            if mint_target_token != "" && pre_sol_balances != 0 && pre_token_balances != 0 {
+            if !mint_target_token.is_empty() && pre_sol_balances != 0 && pre_token_balances != 0 {
                 break;
             }
             if account.owner == RD_AUTHORITY && account.mint != WSOL {
@@ -91,33 +345,21 @@ impl RaydiumType {
             }
         }
 
-        if mint_target_token   == "" ||
+        if mint_target_token.is_empty() ||
            pre_sol_balances    == 0  ||
            pre_token_balances  == 0  ||
            post_sol_balances   == 0  ||
            post_token_balances == 0 {
             return Err(anyhow::anyhow!("Failed to indentify transaction type"));
         }
-        let sol_reserve = post_sol_balances - pre_sol_balances;
-        let token_reserve = post_token_balances - pre_token_balances;
+        let sol_reserve = post_sol_balances.abs_diff(pre_sol_balances);
+        let token_reserve = post_token_balances.abs_diff(pre_token_balances);
         if (post_sol_balances > pre_sol_balances) && (post_token_balances > pre_token_balances) {
-            return Ok(RaydiumType::AddLiquidity(LiquiditySize{sol_reserve, token_reserve}));
+            Ok(RaydiumType::AddLiquidity(LiquiditySize{sol_reserve, token_reserve}))
         } else if (post_sol_balances < pre_sol_balances) && (post_token_balances < pre_token_balances) {
-            return Ok(RaydiumType::RemoveLiquidity(LiquiditySize{sol_reserve, token_reserve}));
-        } else if (post_sol_balances > pre_sol_balances) && (post_token_balances < pre_token_balances) {
-            let price_impact_sqrt = post_sol_balances as f64/pre_sol_balances as f64;
-            let price_impact = (price_impact_sqrt * price_impact_sqrt) - 1.0; 
-            let amount_in = post_sol_balances - pre_sol_balances;
-            let amount_out = pre_token_balances - post_token_balances;
-            return Ok(RaydiumType::Buy(TradeSize{reserve_in : post_sol_balances, reserve_out :post_token_balances, amount_in, amount_out, price_impact}));
-        } else if (post_sol_balances < pre_sol_balances) && (post_token_balances > pre_token_balances) {
-            let price_impact_sqrt = pre_token_balances as f64/ post_token_balances as f64;
-            let price_impact = 1.0 - (price_impact_sqrt * price_impact_sqrt);
-            let amount_in = post_token_balances - pre_token_balances;
-            let amount_out = pre_sol_balances - post_sol_balances;
-            return Ok(RaydiumType::Sell(TradeSize{reserve_in : post_token_balances, reserve_out : post_sol_balances, amount_in, amount_out, price_impact}));
+            Ok(RaydiumType::RemoveLiquidity(LiquiditySize{sol_reserve, token_reserve}))
         } else {
-            return Ok(RaydiumType::Unknown);
+            Ok(RaydiumType::Unknown)
         }
     }
 }
@@ -131,6 +373,10 @@ pub enum PumpType {
 
 #[derive(Debug)]
 pub struct TradeSizeWithVirtual {
+    /// The `user` account of the buy or sell, who may differ from the fee payer
+    user: Pubkey,
+    bonding_curve: Pubkey,
+    mint: Pubkey,
     reserve_in_virtual: u64,
     reserve_out_virtual: u64,
     reserve_in: u64,
@@ -141,27 +387,44 @@ pub struct TradeSizeWithVirtual {
 }
 
 impl PumpType {
-// TODO unfineshed
-    pub fn get_type(ixs : &Vec<PumpfunInstruction>) -> Result<Self> {
+    /// Classifies a single swap: the buy/sell instruction followed by the CPI event
+    /// log it emitted.
+    pub fn get_type(ixs : &[PumpfunInstruction]) -> Result<Self> {
         if ixs.len() != 2 {
             return Ok(PumpType::Unknown);
         }
         let mut is_buy = false;
         let mut decodedCpi = CPILog::default();
+        let mut bonding_curve = Pubkey::default();
+        let mut user = None;
         for ix in ixs {
             match ix {
-                PumpfunInstruction::Buy(_, _) => {is_buy = true},
-                PumpfunInstruction::Sell(_, _) => {is_buy = false},
+                // Buy and sell accounts: global, fee recipient, mint, bonding curve,
+                // associated bonding curve, associated user, user, ...
+                PumpfunInstruction::Buy(_, accounts) => {
+                    is_buy = true;
+                    bonding_curve = accounts.get(3).map(|meta| meta.pubkey).unwrap_or_default();
+                    user = accounts.get(6).map(|meta| meta.pubkey);
+                },
+                PumpfunInstruction::Sell(_, accounts) => {
+                    is_buy = false;
+                    bonding_curve = accounts.get(3).map(|meta| meta.pubkey).unwrap_or_default();
+                    user = accounts.get(6).map(|meta| meta.pubkey);
+                },
                 PumpfunInstruction::CPILog(inner, _) => decodedCpi = inner.clone(),
                 _ => return Ok(PumpType::Unknown)
             }
         }
+        let user = user.unwrap_or_else(|| Pubkey::new_from_array(decodedCpi.user_addrs));
         if is_buy {
             let reserve_in_virtual_before = decodedCpi.virtual_sol_reserves - decodedCpi.user_sol;
             let price_impact_sqrt = decodedCpi.virtual_sol_reserves as f64/reserve_in_virtual_before as f64;
             let price_impact = (price_impact_sqrt * price_impact_sqrt) - 1.0; 
 
             let tradesizevirtual = TradeSizeWithVirtual{
+                user,
+                bonding_curve,
+                mint : Pubkey::new_from_array(decodedCpi.mint_address),
                 reserve_in_virtual : decodedCpi.virtual_sol_reserves,
                 reserve_out_virtual : decodedCpi.virtual_token_reserves,
                 reserve_in : decodedCpi.real_sol_reserves,
@@ -171,12 +434,15 @@ impl PumpType {
                 price_impact 
             };
 
-            return Ok(PumpType::Buy(tradesizevirtual));
+            Ok(PumpType::Buy(tradesizevirtual))
         } else {
             let reserve_in_virtual_before = decodedCpi.virtual_token_reserves - decodedCpi.user_token;
             let price_impact_sqrt = reserve_in_virtual_before as f64/decodedCpi.virtual_token_reserves as f64;
             let price_impact = 1.0 - (price_impact_sqrt * price_impact_sqrt);
             let tradesizevirtual = TradeSizeWithVirtual{
+                user,
+                bonding_curve,
+                mint : Pubkey::new_from_array(decodedCpi.mint_address),
                 reserve_in_virtual : decodedCpi.virtual_token_reserves,
                 reserve_out_virtual : decodedCpi.virtual_sol_reserves,
                 reserve_in : decodedCpi.real_token_reserves,
@@ -186,7 +452,34 @@ impl PumpType {
                 price_impact 
             };
 
-            return Ok(PumpType::Sell(tradesizevirtual));
+            Ok(PumpType::Sell(tradesizevirtual))
         }        
     }
-}
\ No newline at end of file
+
+    /// Normalizes a bonding curve trade into a `TradeEvent`, attributed to its user.
+    pub fn trade_event(&self, signature: &str, slot: u64) -> Option<TradeEvent> {
+        let (side, size) = match self {
+            PumpType::Buy(size) => (Side::Buy, size),
+            PumpType::Sell(size) => (Side::Sell, size),
+            PumpType::Unknown => return None,
+        };
+        let (sol_amount, token_amount, sol_reserve, token_reserve) = match side {
+            Side::Buy => (size.amount_in, size.amount_out, size.reserve_in_virtual, size.reserve_out_virtual),
+            Side::Sell => (size.amount_out, size.amount_in, size.reserve_out_virtual, size.reserve_in_virtual),
+        };
+        Some(TradeEvent {
+            dex: Dex::Pumpfun,
+            signature: signature.to_owned(),
+            slot,
+            wallet: size.user.to_string(),
+            pool: size.bonding_curve.to_string(),
+            mint: size.mint.to_string(),
+            side,
+            sol_amount,
+            token_amount,
+            sol_reserve,
+            token_reserve,
+            price_impact: size.price_impact,
+        })
+    }
+}
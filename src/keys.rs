@@ -0,0 +1,233 @@
+use std::path::Path;
+use aes_gcm_siv::{aead::{Aead, KeyInit}, Aes256GcmSiv, Nonce};
+use anyhow::{anyhow, Context, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use hmac::Hmac;
+use log::{info, warn};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use solana_sdk::signature::{Keypair, Signer};
+use crate::config::{CopierWallet, KeystoreConfig};
+
+const KEYSTORE_ITERATIONS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Keystore file: the keypair bytes encrypted with AES-256-GCM-SIV, under a key
+/// derived from the passphrase with PBKDF2-HMAC-SHA256.
+#[derive(Debug, Serialize, Deserialize)]
+struct Keystore {
+    pubkey: String,
+    iterations: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Loads the copier keypair from the one configured source, or `None` if there is
+/// none.
+pub fn load_copier_keypair(wallet: &CopierWallet) -> Result<Option<Keypair>> {
+    let plaintext = !wallet.private_key.expose().is_empty();
+    let sources = [wallet.keypair_path.is_some(), wallet.key_env.is_some(), wallet.keystore.is_some(), plaintext];
+    if sources.iter().filter(|&&configured| configured).count() > 1 {
+        return Err(anyhow!("Configure only one of keypair_path, key_env, keystore and private_key for the copier"));
+    }
+
+    let keypair = if let Some(path) = &wallet.keypair_path {
+        read_keypair_file(Path::new(path))?
+    } else if let Some(var) = &wallet.key_env {
+        let key = std::env::var(var).with_context(|| format!("Copier key variable {} is not set", var))?;
+        keypair_from_base58(&key)?
+    } else if let Some(keystore) = &wallet.keystore {
+        decrypt_keystore(keystore)?
+    } else if plaintext {
+        if !wallet.allow_plaintext_key {
+            return Err(anyhow!(
+                "Refusing the plaintext copier private_key in the config: use keypair_path, key_env or keystore, or set allow_plaintext_key"
+            ));
+        }
+        warn!("The copier key is read from plaintext in the config");
+        keypair_from_base58(wallet.private_key.expose())?
+    } else {
+        return Ok(None);
+    };
+    info!("Copier wallet {}", keypair.pubkey());
+    Ok(Some(keypair))
+}
+
+/// Solana CLI keypair file: a JSON array of the 64 keypair bytes.
+fn read_keypair_file(path: &Path) -> Result<Keypair> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read keypair file {}", path.display()))?;
+    let bytes: Vec<u8> = serde_json::from_str(&content).with_context(|| format!("Invalid keypair file {}", path.display()))?;
+    Keypair::from_bytes(&bytes).map_err(|_| anyhow!("{} does not hold a valid keypair", path.display()))
+}
+
+fn keypair_from_base58(key: &str) -> Result<Keypair> {
+    let bytes = bs58::decode(key.trim()).into_vec().map_err(|_| anyhow!("Copier private key is not valid base58"))?;
+    Keypair::from_bytes(&bytes).map_err(|_| anyhow!("Copier private key is not a valid keypair"))
+}
+
+fn passphrase(keystore: &KeystoreConfig) -> Result<String> {
+    std::env::var(&keystore.passphrase_env)
+        .with_context(|| format!("Keystore passphrase variable {} is not set", keystore.passphrase_env))
+}
+
+fn cipher(passphrase: &str, salt: &[u8], iterations: u32) -> Result<Aes256GcmSiv> {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, iterations, &mut key);
+    Aes256GcmSiv::new_from_slice(&key).map_err(|_| anyhow!("Invalid keystore key length"))
+}
+
+fn decrypt_keystore(config: &KeystoreConfig) -> Result<Keypair> {
+    let content = std::fs::read_to_string(&config.path).with_context(|| format!("Failed to read keystore {}", config.path))?;
+    let keystore: Keystore = serde_json::from_str(&content).with_context(|| format!("Invalid keystore {}", config.path))?;
+    let salt = BASE64_STANDARD.decode(&keystore.salt).context("Invalid keystore salt")?;
+    let nonce: [u8; NONCE_LEN] = BASE64_STANDARD.decode(&keystore.nonce).context("Invalid keystore nonce")?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid keystore nonce length"))?;
+    let ciphertext = BASE64_STANDARD.decode(&keystore.ciphertext).context("Invalid keystore ciphertext")?;
+    let bytes = cipher(&passphrase(config)?, &salt, keystore.iterations)?
+        .decrypt(&Nonce::from(nonce), ciphertext.as_slice())
+        .map_err(|_| anyhow!("Wrong passphrase for keystore {}", config.path))?;
+    Keypair::from_bytes(&bytes).map_err(|_| anyhow!("Keystore {} does not hold a valid keypair", config.path))
+}
+
+/// Encrypts a Solana CLI keypair file into the configured keystore, with the
+/// passphrase from its environment variable.
+pub fn encrypt_keypair_file(keypair_path: &Path, config: &KeystoreConfig) -> Result<()> {
+    encrypt_keypair(&read_keypair_file(keypair_path)?, config, KEYSTORE_ITERATIONS)
+}
+
+fn encrypt_keypair(keypair: &Keypair, config: &KeystoreConfig, iterations: u32) -> Result<()> {
+    let passphrase = passphrase(config)?;
+    if passphrase.is_empty() {
+        return Err(anyhow!("Keystore passphrase variable {} is empty", config.passphrase_env));
+    }
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher(&passphrase, &salt, iterations)?
+        .encrypt(&Nonce::from(nonce), keypair.to_bytes().as_slice())
+        .map_err(|_| anyhow!("Failed to encrypt the keypair"))?;
+    let keystore = Keystore {
+        pubkey: keypair.pubkey().to_string(),
+        iterations,
+        salt: BASE64_STANDARD.encode(salt),
+        nonce: BASE64_STANDARD.encode(nonce),
+        ciphertext: BASE64_STANDARD.encode(ciphertext),
+    };
+    std::fs::write(&config.path, serde_json::to_string_pretty(&keystore)?)
+        .with_context(|| format!("Failed to write keystore {}", config.path))?;
+    info!("Wrote keystore {} for {}", config.path, keystore.pubkey);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keeps the key derivation quick in unoptimized test builds
+    const TEST_ITERATIONS: u32 = 1_000;
+
+    fn keystore_config(name: &str, passphrase: &str) -> KeystoreConfig {
+        let passphrase_env = format!("COPYTRADE_TEST_PASSPHRASE_{}", name.to_uppercase());
+        std::env::set_var(&passphrase_env, passphrase);
+        KeystoreConfig {
+            path: std::env::temp_dir()
+                .join(format!("copytrade-keystore-{}-{}.json", name, rand::random::<u64>()))
+                .to_string_lossy()
+                .into_owned(),
+            passphrase_env,
+        }
+    }
+
+    fn copier(keystore: KeystoreConfig) -> CopierWallet {
+        CopierWallet {
+            keypair_path: None,
+            key_env: None,
+            keystore: Some(keystore),
+            private_key: Default::default(),
+            allow_plaintext_key: false,
+        }
+    }
+
+    #[test]
+    fn keystore_round_trip() {
+        let keypair = Keypair::new();
+        let config = keystore_config("round_trip", "correct horse");
+        encrypt_keypair(&keypair, &config, TEST_ITERATIONS).unwrap();
+
+        let content = std::fs::read_to_string(&config.path).unwrap();
+        assert!(!content.contains(&keypair.to_base58_string()));
+        let loaded = load_copier_keypair(&copier(config.clone())).unwrap().unwrap();
+        assert_eq!(loaded.to_bytes(), keypair.to_bytes());
+        std::fs::remove_file(&config.path).unwrap();
+    }
+
+    #[test]
+    fn keystore_rejects_a_wrong_passphrase() {
+        let config = keystore_config("wrong_passphrase", "correct horse");
+        encrypt_keypair(&Keypair::new(), &config, TEST_ITERATIONS).unwrap();
+
+        std::env::set_var(&config.passphrase_env, "battery staple");
+        let error = load_copier_keypair(&copier(config.clone())).unwrap_err();
+        assert!(error.to_string().contains("Wrong passphrase"));
+        std::fs::remove_file(&config.path).unwrap();
+    }
+
+    #[test]
+    fn keystore_rejects_a_tampered_ciphertext() {
+        let config = keystore_config("tampered", "correct horse");
+        encrypt_keypair(&Keypair::new(), &config, TEST_ITERATIONS).unwrap();
+
+        let mut keystore: Keystore = serde_json::from_str(&std::fs::read_to_string(&config.path).unwrap()).unwrap();
+        let mut ciphertext = BASE64_STANDARD.decode(&keystore.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        keystore.ciphertext = BASE64_STANDARD.encode(ciphertext);
+        std::fs::write(&config.path, serde_json::to_string(&keystore).unwrap()).unwrap();
+
+        assert!(load_copier_keypair(&copier(config.clone())).is_err());
+        std::fs::remove_file(&config.path).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_plaintext_key_unless_allowed() {
+        let keypair = Keypair::new();
+        let mut wallet: CopierWallet = serde_json::from_value(serde_json::json!({
+            "keypair_path": null,
+            "key_env": null,
+            "keystore": null,
+            "private_key": keypair.to_base58_string(),
+            "allow_plaintext_key": false,
+        })).unwrap();
+        let error = load_copier_keypair(&wallet).unwrap_err();
+        assert!(error.to_string().contains("Refusing the plaintext"));
+
+        wallet.allow_plaintext_key = true;
+        assert_eq!(load_copier_keypair(&wallet).unwrap().unwrap().pubkey(), keypair.pubkey());
+    }
+
+    #[test]
+    fn a_configured_keystore_is_required() {
+        // A keystore that can't be read fails the load instead of running without a key
+        let config = keystore_config("missing", "correct horse");
+        assert!(load_copier_keypair(&copier(config.clone())).is_err());
+
+        // The passphrase has to be set, for encrypting and for decrypting
+        encrypt_keypair(&Keypair::new(), &config, TEST_ITERATIONS).unwrap();
+        std::env::remove_var(&config.passphrase_env);
+        let error = load_copier_keypair(&copier(config.clone())).unwrap_err();
+        assert!(error.to_string().contains("passphrase variable"));
+        std::env::set_var(&config.passphrase_env, "");
+        assert!(encrypt_keypair(&Keypair::new(), &config, TEST_ITERATIONS).is_err());
+
+        // And it can't be combined with another key source
+        let mut wallet = copier(config.clone());
+        wallet.key_env = Some("COPYTRADE_TEST_UNUSED_KEY".to_owned());
+        let error = load_copier_keypair(&wallet).unwrap_err();
+        assert!(error.to_string().contains("Configure only one"));
+        std::fs::remove_file(&config.path).unwrap();
+    }
+}
@@ -1,17 +1,58 @@
+mod backfill;
+mod backtest;
+mod backoff;
+mod chain_cache;
 mod config;
+mod confirmation;
+mod copier;
+mod copy_filter;
+mod dedup;
 mod event_loops;
+mod execution;
 mod parser;
+mod pools;
+mod positions;
+mod quote;
+mod recorder;
+mod resolver;
+mod resume;
+mod risk;
+mod rpc;
+mod safety;
+mod sender;
+mod slots;
+mod targets;
+mod trade;
 mod filter;
+mod keys;
+mod metrics;
+mod mints;
+mod undecoded;
 mod utils { 
     pub mod raydium_parser;
     pub mod pumpfun_parser;
 }
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use log::info;
 use std::fs::OpenOptions;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::signal::unix::{signal, SignalKind};
+use crate::backfill::start_backfill;
 use crate::config::Config;
+use crate::copier::Copier;
+use crate::execution::Executor;
+use crate::keys::encrypt_keypair_file;
 use crate::event_loops::start_monitor_loop;
+use crate::metrics::start_metrics_reporter;
+use crate::mints::MintResolver;
+use crate::positions::PositionBook;
+use crate::quote::pumpfun::set_fee_basis_points;
+use crate::resolver::PoolResolver;
+use crate::risk::RiskManager;
+use crate::targets::{start_target_reload, TARGETS};
+use crate::undecoded::UndecodedSink;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -34,15 +75,54 @@ async fn main() -> Result<()> {
     
     // Load configuration
     let config = Config::load()?;
+    set_fee_basis_points(config.dex.pumpfun.fee_basis_points);
+
+    if std::env::args().nth(1).as_deref() == Some("backtest") {
+        return backtest::run(&config);
+    }
+    if std::env::args().nth(1).as_deref() == Some("encrypt-key") {
+        let keypair_path = std::env::args().nth(2).ok_or_else(|| anyhow!("Usage: encrypt-key <keypair.json>"))?;
+        let keystore = config.wallets.copier.keystore.as_ref()
+            .ok_or_else(|| anyhow!("Configure wallets.copier.keystore to encrypt a key"))?;
+        return encrypt_keypair_file(Path::new(&keypair_path), keystore);
+    }
+
+    TARGETS.set(config.wallets.targets.clone());
+    let undecoded = Arc::new(UndecodedSink::open(&config.logging.undecoded_path)?);
+    start_metrics_reporter(config.logging.metrics_interval_secs);
+    let positions = Arc::new(Mutex::new(PositionBook::default()));
+    let resolver = Arc::new(PoolResolver::new(&config)?);
+    let mints = Arc::new(MintResolver::new(&config));
+    let executor = Executor::from_config(&config, resolver.clone(), mints.clone())?.map(Arc::new);
+    let risk = Arc::new(RiskManager::load(&config)?);
+    risk.start();
+    let copier = Arc::new(Copier::new(&config, positions.clone(), resolver, mints.clone(), risk.clone(), executor));
+    start_backfill(&config, TARGETS.active_wallets(), positions.clone(), mints.clone());
+    start_target_reload(config.clone(), positions, mints);
 
     // Start the monitoring loop
-    start_monitor_loop(config.clone(), 0).await?;  // RAYDIUM
-    start_monitor_loop(config, 1).await?;  // PUMPFUN
+    let checkpoints = [
+        start_monitor_loop(config.clone(), 0, undecoded.clone(), copier.clone()).await?,  // RAYDIUM
+        start_monitor_loop(config, 1, undecoded, copier).await?,  // PUMPFUN
+    ];
 
-    // Keep the main task running
-    loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    // Run until asked to stop, then persist the progress of the monitors
+    shutdown_signal().await?;
+    info!("Copytrade shutting down");
+    for checkpoint in checkpoints.iter().flatten() {
+        checkpoint.flush();
     }
+    risk.flush();
+    Ok(())
+}
 
+/// Resolves on Ctrl-C or SIGTERM.
+async fn shutdown_signal() -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
+    }
+    Ok(())
 }
 
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use log::info;
+use crate::pools::POOLS;
+use tokio::time::{sleep, Duration};
+
+/// Process-wide counters, shared by every monitor loop.
+pub struct Metrics {
+    pub transactions_received: AtomicU64,
+    pub transactions_decoded: AtomicU64,
+    pub transactions_undecoded: AtomicU64,
+    pub trades_detected: AtomicU64,
+    pub trades_confirmed: AtomicU64,
+    pub trades_orphaned: AtomicU64,
+    pub copies_blocked: AtomicU64,
+    pub copies_landed: AtomicU64,
+    pub copies_failed: AtomicU64,
+    pub copies_expired: AtomicU64,
+    feeds: Mutex<BTreeMap<String, FeedStats>>,
+}
+
+/// Arrival statistics of one gRPC feed, to compare providers' latency.
+#[derive(Debug, Default, Clone)]
+pub struct FeedStats {
+    pub first_arrivals: u64,
+    pub late_arrivals: u64,
+    pub total_lag_us: u64,
+}
+
+pub static METRICS: Metrics = Metrics {
+    transactions_received: AtomicU64::new(0),
+    transactions_decoded: AtomicU64::new(0),
+    transactions_undecoded: AtomicU64::new(0),
+    trades_detected: AtomicU64::new(0),
+    trades_confirmed: AtomicU64::new(0),
+    trades_orphaned: AtomicU64::new(0),
+    copies_blocked: AtomicU64::new(0),
+    copies_landed: AtomicU64::new(0),
+    copies_failed: AtomicU64::new(0),
+    copies_expired: AtomicU64::new(0),
+    feeds: Mutex::new(BTreeMap::new()),
+};
+
+impl Metrics {
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_first_arrival(&self, feed: &str) {
+        self.with_feed(feed, |stats| stats.first_arrivals += 1);
+    }
+
+    pub fn record_late_arrival(&self, feed: &str, lag: Duration) {
+        self.with_feed(feed, |stats| {
+            stats.late_arrivals += 1;
+            stats.total_lag_us += lag.as_micros() as u64;
+        });
+    }
+
+    fn with_feed(&self, feed: &str, update: impl FnOnce(&mut FeedStats)) {
+        let mut feeds = match self.feeds.lock() {
+            Ok(feeds) => feeds,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        update(feeds.entry(feed.to_owned()).or_default());
+    }
+
+    pub fn log(&self) {
+        info!(
+            "METRICS received={} decoded={} undecoded={} trades={} confirmed={} orphaned={} blocked={} landed={} failed={} expired={}",
+            self.transactions_received.load(Ordering::Relaxed),
+            self.transactions_decoded.load(Ordering::Relaxed),
+            self.transactions_undecoded.load(Ordering::Relaxed),
+            self.trades_detected.load(Ordering::Relaxed),
+            self.trades_confirmed.load(Ordering::Relaxed),
+            self.trades_orphaned.load(Ordering::Relaxed),
+            self.copies_blocked.load(Ordering::Relaxed),
+            self.copies_landed.load(Ordering::Relaxed),
+            self.copies_failed.load(Ordering::Relaxed),
+            self.copies_expired.load(Ordering::Relaxed),
+        );
+        info!("METRICS pools_cached={}", POOLS.len());
+        let feeds = match self.feeds.lock() {
+            Ok(feeds) => feeds.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        for (feed, stats) in feeds {
+            let avg_lag_us = stats.total_lag_us.checked_div(stats.late_arrivals).unwrap_or_default();
+            info!(
+                "METRICS feed={} first_arrivals={} late_arrivals={} avg_lag_us={}",
+                feed, stats.first_arrivals, stats.late_arrivals, avg_lag_us,
+            );
+        }
+    }
+}
+
+/// Logs a metrics line every `interval_secs` seconds.
+pub fn start_metrics_reporter(interval_secs: u64) {
+    tokio::spawn(async move {
+        loop {
+            sleep(Duration::from_secs(interval_secs)).await;
+            METRICS.log();
+        }
+    });
+}
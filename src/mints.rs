@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use anyhow::{anyhow, Result};
+use log::info;
+use solana_sdk::{pubkey, pubkey::Pubkey};
+use crate::config::Config;
+use crate::rpc::{ChainRpc, RpcClient};
+use crate::trade::{Side, TradeEvent};
+
+pub const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+pub const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP7VEwdNRMdzXdMg8oQL7BYbDEE4X2U");
+
+/// SPL `Mint`: mint authority, supply, decimals, initialized flag, freeze authority.
+/// Authorities are a `COption`: a u32 tag, then the pubkey.
+const MINT_LEN: usize = 82;
+const MINT_AUTHORITY: usize = 0;
+const MINT_SUPPLY: usize = 36;
+const MINT_FREEZE_AUTHORITY: usize = 46;
+/// Token-2022 extensions follow the base state padded to the size of a token
+/// account, and a one byte account type.
+const EXTENSIONS_START: usize = 166;
+const EXTENSION_TRANSFER_FEE_CONFIG: u16 = 1;
+/// `TransferFeeConfig`: two authorities and the withheld amount, then the older and
+/// newer `TransferFee`.
+const TRANSFER_FEE_OLDER: usize = 72;
+const TRANSFER_FEE_NEWER: usize = 90;
+const SLOTS_PER_EPOCH: u64 = 432_000;
+
+/// Transfer fee of a Token-2022 mint from `epoch` on.
+#[derive(Debug, Clone, Copy)]
+pub struct TransferFee {
+    pub epoch: u64,
+    pub maximum_fee: u64,
+    pub basis_points: u16,
+}
+
+impl TransferFee {
+    /// Fee withheld from a transfer of `amount`, rounded up like the program does.
+    pub fn fee(&self, amount: u64) -> u64 {
+        if self.basis_points == 0 || amount == 0 {
+            return 0;
+        }
+        let fee = (amount as u128 * self.basis_points as u128).div_ceil(10_000);
+        fee.min(self.maximum_fee as u128) as u64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MintInfo {
+    /// SPL Token or Token-2022, the owner of the mint and of all its token accounts
+    pub token_program: Pubkey,
+    pub supply: u64,
+    pub mint_authority: Option<Pubkey>,
+    pub freeze_authority: Option<Pubkey>,
+    /// Token-2022 only: the older fee applies until the newer one's epoch
+    pub transfer_fees: Option<(TransferFee, TransferFee)>,
+}
+
+impl MintInfo {
+    pub fn parse(owner: Pubkey, data: &[u8]) -> Result<Self> {
+        if owner != TOKEN_PROGRAM_ID && owner != TOKEN_2022_PROGRAM_ID {
+            return Err(anyhow!("Mint is owned by {}, not a token program", owner));
+        }
+        if data.len() < MINT_LEN {
+            return Err(anyhow!("Mint data too short: {} bytes", data.len()));
+        }
+        let mut transfer_fees = None;
+        let mut offset = EXTENSIONS_START;
+        while owner == TOKEN_2022_PROGRAM_ID && offset + 4 <= data.len() {
+            let extension = u16::from_le_bytes([data[offset], data[offset + 1]]);
+            let length = u16::from_le_bytes([data[offset + 2], data[offset + 3]]) as usize;
+            let value = data.get(offset + 4..offset + 4 + length)
+                .ok_or_else(|| anyhow!("Truncated mint extension {}", extension))?;
+            if extension == EXTENSION_TRANSFER_FEE_CONFIG {
+                transfer_fees = Some((transfer_fee_at(value, TRANSFER_FEE_OLDER)?, transfer_fee_at(value, TRANSFER_FEE_NEWER)?));
+            }
+            offset += 4 + length;
+        }
+        Ok(Self {
+            token_program: owner,
+            supply: u64::from_le_bytes(data[MINT_SUPPLY..MINT_SUPPLY + 8].try_into()?),
+            mint_authority: authority_at(data, MINT_AUTHORITY),
+            freeze_authority: authority_at(data, MINT_FREEZE_AUTHORITY),
+            transfer_fees,
+        })
+    }
+
+    pub fn is_token_2022(&self) -> bool {
+        self.token_program == TOKEN_2022_PROGRAM_ID
+    }
+
+    /// Fee withheld from a transfer of `amount` made at `slot`.
+    pub fn transfer_fee(&self, amount: u64, slot: u64) -> u64 {
+        let Some((older, newer)) = self.transfer_fees else {
+            return 0;
+        };
+        match slot / SLOTS_PER_EPOCH >= newer.epoch {
+            true => newer.fee(amount),
+            false => older.fee(amount),
+        }
+    }
+}
+
+fn authority_at(data: &[u8], offset: usize) -> Option<Pubkey> {
+    match data[offset..offset + 4] {
+        [0, 0, 0, 0] => None,
+        _ => Some(Pubkey::new_from_array(data[offset + 4..offset + 36].try_into().ok()?)),
+    }
+}
+
+fn transfer_fee_at(value: &[u8], offset: usize) -> Result<TransferFee> {
+    let field = |start: usize, len: usize| value.get(offset + start..offset + start + len)
+        .ok_or_else(|| anyhow!("Transfer fee config too short"));
+    Ok(TransferFee {
+        epoch: u64::from_le_bytes(field(0, 8)?.try_into()?),
+        maximum_fee: u64::from_le_bytes(field(8, 8)?.try_into()?),
+        basis_points: u16::from_le_bytes(field(16, 2)?.try_into()?),
+    })
+}
+
+/// Fetches and caches the token program and transfer fees of every mint we copy,
+/// since targets trade both SPL Token and Token-2022 mints.
+pub struct MintResolver {
+    rpc: Arc<dyn ChainRpc>,
+    cache: Mutex<HashMap<Pubkey, Arc<MintInfo>>>,
+}
+
+impl MintResolver {
+    pub fn new(config: &Config) -> Self {
+        Self::with_rpc(Arc::new(RpcClient::new(&config.rpc.endpoint)))
+    }
+
+    pub fn with_rpc(rpc: Arc<dyn ChainRpc>) -> Self {
+        Self { rpc, cache: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn cached(&self, mint: &Pubkey) -> Option<Arc<MintInfo>> {
+        match self.cache.lock() {
+            Ok(cache) => cache.get(mint).cloned(),
+            Err(poisoned) => poisoned.into_inner().get(mint).cloned(),
+        }
+    }
+
+    pub async fn resolve(&self, mint: &Pubkey) -> Result<Arc<MintInfo>> {
+        if let Some(info) = self.cached(mint) {
+            return Ok(info);
+        }
+        let account = self.rpc.get_account(mint).await?
+            .ok_or_else(|| anyhow!("Mint {} not found", mint))?;
+        let info = Arc::new(MintInfo::parse(account.owner, &account.data)?);
+        if info.is_token_2022() {
+            info!("Mint {} is a Token-2022 mint (transfer fees: {:?})", mint, info.transfer_fees);
+        }
+        match self.cache.lock() {
+            Ok(mut cache) => cache.insert(*mint, info.clone()),
+            Err(poisoned) => poisoned.into_inner().insert(*mint, info.clone()),
+        };
+        Ok(info)
+    }
+
+    /// Transfer fee withheld from `amount` tokens of `mint` moved at `slot`, once the
+    /// mint is resolved.
+    pub fn transfer_fee(&self, mint: &str, amount: u64, slot: u64) -> u64 {
+        Pubkey::from_str(mint).ok()
+            .and_then(|mint| self.cached(&mint))
+            .map(|info| info.transfer_fee(amount, slot))
+            .unwrap_or(0)
+    }
+
+    /// The trade as the wallet's token account saw it: a buy of a Token-2022 mint with
+    /// a transfer fee credits the tokens out of the pool minus the fee. Decoders report
+    /// the tokens that left the pool, the fee is only taken off here.
+    pub fn received(&self, trade: &TradeEvent) -> TradeEvent {
+        let mut trade = trade.clone();
+        if trade.side == Side::Buy {
+            trade.token_amount -= self.transfer_fee(&trade.mint, trade.token_amount, trade.slot);
+        }
+        trade
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUTHORITY: Pubkey = pubkey!("9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM");
+
+    /// Base mint state with a mint authority, `supply` and no freeze authority.
+    fn mint(supply: u64) -> Vec<u8> {
+        let mut data = vec![0u8; MINT_LEN];
+        data[0] = 1;
+        data[4..36].copy_from_slice(AUTHORITY.as_ref());
+        data[36..44].copy_from_slice(&supply.to_le_bytes());
+        data[44] = 6;
+        data[45] = 1;
+        data
+    }
+
+    fn transfer_fee(epoch: u64, maximum_fee: u64, basis_points: u16) -> Vec<u8> {
+        [epoch.to_le_bytes().as_slice(), &maximum_fee.to_le_bytes(), &basis_points.to_le_bytes()].concat()
+    }
+
+    /// Token-2022 mint with a close authority extension, then a transfer fee config
+    /// moving from 1% (capped at 5000) to 0.5% (capped at 1000) at epoch 500.
+    fn token_2022_mint() -> Vec<u8> {
+        let mut data = mint(1_000_000);
+        data.resize(EXTENSIONS_START - 1, 0);
+        data.push(1);
+        // MintCloseAuthority
+        data.extend_from_slice(&3u16.to_le_bytes());
+        data.extend_from_slice(&32u16.to_le_bytes());
+        data.extend_from_slice(AUTHORITY.as_ref());
+        // TransferFeeConfig: authorities, withheld amount, older and newer fee
+        data.extend_from_slice(&EXTENSION_TRANSFER_FEE_CONFIG.to_le_bytes());
+        data.extend_from_slice(&108u16.to_le_bytes());
+        data.extend_from_slice(&[0u8; 72]);
+        data.extend(transfer_fee(0, 5_000, 100));
+        data.extend(transfer_fee(500, 1_000, 50));
+        data
+    }
+
+    #[test]
+    fn parses_a_plain_spl_mint() {
+        let info = MintInfo::parse(TOKEN_PROGRAM_ID, &mint(42)).unwrap();
+        assert!(!info.is_token_2022());
+        assert_eq!(info.supply, 42);
+        assert_eq!(info.mint_authority, Some(AUTHORITY));
+        assert_eq!(info.freeze_authority, None);
+        assert!(info.transfer_fees.is_none());
+        assert_eq!(info.transfer_fee(1_000_000, 0), 0);
+
+        assert!(MintInfo::parse(Pubkey::new_unique(), &mint(42)).is_err());
+        assert!(MintInfo::parse(TOKEN_PROGRAM_ID, &mint(42)[..MINT_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn parses_the_transfer_fee_config_of_a_token_2022_mint() {
+        let info = MintInfo::parse(TOKEN_2022_PROGRAM_ID, &token_2022_mint()).unwrap();
+        assert!(info.is_token_2022());
+        assert_eq!(info.supply, 1_000_000);
+        let (older, newer) = info.transfer_fees.unwrap();
+        assert_eq!((older.epoch, older.maximum_fee, older.basis_points), (0, 5_000, 100));
+        assert_eq!((newer.epoch, newer.maximum_fee, newer.basis_points), (500, 1_000, 50));
+
+        let truncated = &token_2022_mint()[..EXTENSIONS_START + 40];
+        assert!(MintInfo::parse(TOKEN_2022_PROGRAM_ID, truncated).is_err());
+    }
+
+    #[test]
+    fn the_newer_fee_applies_from_its_epoch() {
+        let info = MintInfo::parse(TOKEN_2022_PROGRAM_ID, &token_2022_mint()).unwrap();
+        let switch = 500 * SLOTS_PER_EPOCH;
+        assert_eq!(info.transfer_fee(10_000, switch - 1), 100);
+        assert_eq!(info.transfer_fee(10_000, switch), 50);
+    }
+
+    #[test]
+    fn fees_round_up_and_are_capped() {
+        let fee = TransferFee { epoch: 0, maximum_fee: 5_000, basis_points: 100 };
+        // 1% of 10_001 is 100.01
+        assert_eq!(fee.fee(10_001), 101);
+        assert_eq!(fee.fee(0), 0);
+        assert_eq!(fee.fee(10_000_000), 5_000);
+        assert_eq!(TransferFee { basis_points: 0, ..fee }.fee(10_000), 0);
+    }
+}
@@ -1,126 +1,192 @@
-use serde_json::{Value, json};
-use log::{info};
+use log::{info, warn};
 use solana_transaction_status::TransactionWithStatusMeta;
 use anyhow::{Result};
-use solana_sdk::{message::v0::LoadedAddresses, instruction::{Instruction, AccountMeta, CompiledInstruction}};
+use solana_sdk::{message::v0::LoadedAddresses, instruction::{Instruction, AccountMeta, CompiledInstruction}, pubkey::Pubkey};
 use crate::utils::{raydium_parser::*, pumpfun_parser::*};
-use crate::filter::{RaydiumType, PumpType};
+use crate::filter::{RaydiumType, PumpType, SwapLeg};
+use crate::pools::{PoolState, POOLS};
+use crate::quote::pumpfun::set_fee_basis_points;
+use crate::trade::{Dex, TradeEvent};
 
-pub fn decode_raydium_txn(tx: &TransactionWithStatusMeta) -> Result<Value> {
-    let allIxs = flatten_transaction_response(&tx.clone())?;
+/// An instruction from a transaction, annotated with the top-level instruction it
+/// belongs to and its depth in the CPI stack (1 for top-level instructions).
+#[derive(Debug, Clone)]
+pub struct FlattenedInstruction {
+    pub outer_index: usize,
+    pub stack_height: u32,
+    pub instruction: Instruction,
+}
 
-    let raydiumIxs = allIxs.into_iter().filter(|ix| 
-        ix.program_id == RAYDIUM_AMM_V4_PROGRAM_ID
-    );
+pub fn decode_raydium_txn(tx: &TransactionWithStatusMeta, slot: u64) -> Result<Vec<TradeEvent>> {
+    let all_ixs = flatten_transaction_response(tx)?;
+    let account_keys = transaction_account_keys(tx)?;
+    let signature = tx.transaction_signature().to_string();
 
-    let mut decodedIxs = vec![];
-    for ix in raydiumIxs {
-        decodedIxs.push(RaydiumAmmParser::parse_instruction(&ix)?);
+    // Every swap instruction is reconstructed as its own leg from the token transfers
+    // it invoked, so batched swaps and multi-hop routes are classified independently.
+    // A leg that can't be reconstructed is kept as a gap instead of losing the others.
+    let mut legs = vec![];
+    for (idx, ix) in all_ixs.iter().enumerate() {
+        if ix.instruction.program_id != RAYDIUM_AMM_V4_PROGRAM_ID {
+            continue;
+        }
+        let leg = match RaydiumAmmParser::parse_instruction(&ix.instruction)? {
+            RaydiumInstruction::SwapIn(_, accounts) => {
+                SwapLeg::from_swap(tx, &account_keys, &accounts, cpi_children(&all_ixs, idx), false)
+            }
+            RaydiumInstruction::SwapOut(_, accounts) => {
+                SwapLeg::from_swap(tx, &account_keys, &accounts, cpi_children(&all_ixs, idx), true)
+            }
+            decoded => {
+                info!("RAYDIUM DECODED {:?}", decoded);
+                continue;
+            }
+        };
+        legs.push(leg.map_err(|e| warn!("Skipped a Raydium swap of {}: {:#}", signature, e)).ok());
     }
 
-    let raydiumType = RaydiumType::get_type(&tx.clone())?;
-    info!("RAYDIUM {:?} DECODED {:#?}", raydiumType, decodedIxs);
+    let raydium_types = RaydiumType::get_types(tx, &legs)?;
+    info!("RAYDIUM {:#?} LEGS {:#?}", raydium_types, legs);
 
-    Ok(json!({}))
+    for leg in legs.iter().flatten() {
+        if let Some(state) = leg.pool_state(slot, &signature) {
+            POOLS.update(&leg.pool.to_string(), state);
+        }
+    }
+    Ok(raydium_types.iter().filter_map(|raydium_type| raydium_type.trade_event(&signature, slot)).collect())
 }
 
-pub fn decode_pumpfun_txn(tx: &TransactionWithStatusMeta) -> Result<Value> {
-    let allIxs = flatten_transaction_response(tx)?;
+/// Decodes the Pump.fun swaps of a transaction. `apply_params` controls whether a
+/// `SetParams` instruction updates the global fee; historical decoding (backfill)
+/// passes false so old transactions can't roll the live fee back.
+pub fn decode_pumpfun_txn(tx: &TransactionWithStatusMeta, slot: u64, apply_params: bool) -> Result<Vec<TradeEvent>> {
+    let all_ixs = flatten_transaction_response(tx)?;
+    let signature = tx.transaction_signature().to_string();
 
-    let pumpfunIxs = allIxs.into_iter().filter(|ix| 
-        ix.program_id == PUMPFUN_PROGRAM_ID
-    );
+    // Pair every buy/sell with the CPI event log emitted underneath it, so that a
+    // transaction with several swaps yields one classification per swap
+    let mut pump_types = vec![];
+    for (idx, ix) in all_ixs.iter().enumerate() {
+        if ix.instruction.program_id != PUMPFUN_PROGRAM_ID {
+            continue;
+        }
+        let swap = match PumpfunParser::parse_instruction(&ix.instruction)? {
+            swap @ (PumpfunInstruction::Buy(..) | PumpfunInstruction::Sell(..)) => swap,
+            PumpfunInstruction::SetParams(args, _) if apply_params => {
+                info!("PUMP fee changed to {} bps", args.fee_basis_points);
+                set_fee_basis_points(args.fee_basis_points);
+                continue;
+            }
+            _ => continue,
+        };
+        let cpi_log = cpi_children(&all_ixs, idx)
+            .iter()
+            .filter(|child| child.instruction.program_id == PUMPFUN_PROGRAM_ID)
+            .find_map(|child| match PumpfunParser::parse_instruction(&child.instruction) {
+                Ok(log @ PumpfunInstruction::CPILog(..)) => Some(log),
+                _ => None,
+            });
 
-    let mut decodedIxs = vec![];
-    for ix in pumpfunIxs {
-        decodedIxs.push(PumpfunParser::parse_instruction(&ix)?);
+        if let (Some(PumpfunInstruction::CPILog(log, _)), PumpfunInstruction::Buy(_, accounts) | PumpfunInstruction::Sell(_, accounts)) = (&cpi_log, &swap) {
+            // Buy and sell accounts: global, fee recipient, mint, bonding curve, ...
+            if let Some(bonding_curve) = accounts.get(3) {
+                POOLS.update(&bonding_curve.pubkey.to_string(), PoolState {
+                    dex: Dex::Pumpfun,
+                    mint: Pubkey::new_from_array(log.mint_address).to_string(),
+                    sol_reserve: log.virtual_sol_reserves,
+                    token_reserve: log.virtual_token_reserves,
+                    real_token_reserve: Some(log.real_token_reserves),
+                    slot,
+                    signature: Some(signature.clone()),
+                    vaults: None,
+                });
+            }
+        }
+        let mut swap_ixs = vec![swap];
+        swap_ixs.extend(cpi_log);
+        pump_types.push(PumpType::get_type(&swap_ixs)?);
     }
 
-    let pumpType = PumpType::get_type(&decodedIxs.clone())?;
-    info!("PUMP DECODED {:#?}", pumpType);
+    info!("PUMP DECODED {:#?}", pump_types);
+
+    Ok(pump_types.iter().filter_map(|pump_type| pump_type.trade_event(&signature, slot)).collect())
+}
 
-    Ok(json!({}))
+/// Returns the instructions invoked (directly or transitively) by the instruction at
+/// `parent`, i.e. the run of deeper instructions that follows it in the same
+/// top-level instruction.
+pub fn cpi_children(ixs: &[FlattenedInstruction], parent: usize) -> &[FlattenedInstruction] {
+    let Some(parent_ix) = ixs.get(parent) else {
+        return &[];
+    };
+    let start = parent + 1;
+    let end = ixs[start..]
+        .iter()
+        .position(|ix| ix.outer_index != parent_ix.outer_index || ix.stack_height <= parent_ix.stack_height)
+        .map_or(ixs.len(), |offset| start + offset);
+    &ixs[start..end]
 }
 
-pub fn flatten_transaction_response(tx: &TransactionWithStatusMeta) -> Result<Vec<Instruction>> {
+pub fn flatten_transaction_response(tx: &TransactionWithStatusMeta) -> Result<Vec<FlattenedInstruction>> {
     let mut result = vec![];
     
-    let (message, mut meta) = match tx.clone() {
+    let (message, meta) = match tx {
         TransactionWithStatusMeta::Complete(tx_inner) => {
-            (tx_inner.transaction.message, Some(tx_inner.meta))
+            (&tx_inner.transaction.message, &tx_inner.meta)
         }
-        TransactionWithStatusMeta::MissingMetadata(tx_inner) => {
+        TransactionWithStatusMeta::MissingMetadata(_) => {
             return Err(anyhow::anyhow!("MissingMetadata"));
         }
     };
 
-    let compiled_instructions = match &message {
+    let compiled_instructions = match message {
         solana_sdk::message::VersionedMessage::Legacy(message) => &message.instructions,
         solana_sdk::message::VersionedMessage::V0(message) => &message.instructions,
     };
 
-    let (loaded_addresses, mut inner_instructions) = match &mut meta {
-        Some(meta) => {
-            match &mut meta.inner_instructions {
-                Some(ixs) => {
-                    (Some(&meta.loaded_addresses), Some(ixs))
-                }
-                None => {
-                    (Some(&meta.loaded_addresses), None)
-                }
-            }
-        }
-        None => (None, None)
-    };
-
     let accounts_meta = parse_transaction_accounts(
-        &message,
-        loaded_addresses
+        message,
+        Some(&meta.loaded_addresses)
     );
-    /*const orderedCII = (transaction?.meta?.innerInstructions || []).sort(
-      (a, b) => a.index - b.index,
-    );*/
-    // TODO: sort inner instructions
-    let ordered_cii = match &mut inner_instructions { 
-        Some(ixs) => {
-            ixs.sort_by_key(|cii| cii.index);
-            ixs
-        },
-        None => &vec![]
-    };
 
-    //info!("ordered_cii: {:#?}", ordered_cii);
+    let mut ordered_cii: Vec<_> = meta.inner_instructions.iter().flatten().collect();
+    ordered_cii.sort_by_key(|cii| cii.index);
 
-    let total_calls = ordered_cii.iter().fold(compiled_instructions.len(), |acc, cii| acc + cii.instructions.len());
-    let mut last_pushed_ix = 0;
-    let mut call_index = 0;
-
-    for cii in ordered_cii {
-        while last_pushed_ix != cii.index {
-            call_index += 1;
-            result.push(
-                compiled_instruction_to_instruction(&compiled_instructions[last_pushed_ix as usize], &accounts_meta)?
-            );
-            last_pushed_ix += 1;
-        }
-        for cii_entry in &cii.instructions {
-            call_index += 1;
-            result.push(
-                compiled_instruction_to_instruction(&cii_entry.instruction, &accounts_meta)?
-            );
+    // Each top-level instruction is followed by the inner instructions it invoked, in
+    // execution order. Older nodes don't report a stack height for inner instructions,
+    // in which case they are assumed to be direct CPIs (height 2).
+    for (outer_index, compiled_instruction) in compiled_instructions.iter().enumerate() {
+        result.push(FlattenedInstruction {
+            outer_index,
+            stack_height: 1,
+            instruction: compiled_instruction_to_instruction(compiled_instruction, &accounts_meta)?,
+        });
+        for cii in ordered_cii.iter().filter(|cii| cii.index as usize == outer_index) {
+            for cii_entry in &cii.instructions {
+                result.push(FlattenedInstruction {
+                    outer_index,
+                    stack_height: cii_entry.stack_height.unwrap_or(2),
+                    instruction: compiled_instruction_to_instruction(&cii_entry.instruction, &accounts_meta)?,
+                });
+            }
         }
     }
-    while call_index < total_calls {
-        call_index += 1;
-        result.push(
-            compiled_instruction_to_instruction(&compiled_instructions[last_pushed_ix as usize], &accounts_meta)?
-        );
-        last_pushed_ix += 1;
-    }
-        
+
     Ok(result)
 }
 
+/// Returns the full account list of a transaction (static keys followed by the keys
+/// loaded from lookup tables), in the order used by `account_index` in token balances.
+pub fn transaction_account_keys(tx: &TransactionWithStatusMeta) -> Result<Vec<Pubkey>> {
+    match tx {
+        TransactionWithStatusMeta::Complete(tx_inner) => Ok(parse_transaction_accounts(
+            &tx_inner.transaction.message,
+            Some(&tx_inner.meta.loaded_addresses),
+        ).into_iter().map(|meta| meta.pubkey).collect()),
+        TransactionWithStatusMeta::MissingMetadata(_) => Err(anyhow::anyhow!("MissingMetadata")),
+    }
+}
+
 fn parse_transaction_accounts(
     message: &solana_sdk::message::VersionedMessage,
     loaded_addresses: Option<&LoadedAddresses>,
@@ -146,7 +212,7 @@ fn parse_transaction_accounts(
             AccountMeta {
                 is_signer: idx < required_signatures_accounts_count,
                 is_writable,
-                pubkey: account.clone(),
+                pubkey: *account,
             }
         })
         .collect();
@@ -155,12 +221,12 @@ fn parse_transaction_accounts(
         parsed_accounts.extend(loaded_addresses.writable.iter().map(|pubkey| AccountMeta {
             is_signer: false,
             is_writable: true,
-            pubkey: pubkey.clone(),
+            pubkey: *pubkey,
         }));
         parsed_accounts.extend(loaded_addresses.readonly.iter().map(|pubkey| AccountMeta {
             is_signer: false,
             is_writable: false,
-            pubkey: pubkey.clone(),
+            pubkey: *pubkey,
         }));
     }
 
@@ -193,4 +259,256 @@ fn compiled_instruction_to_instruction(
         accounts,
         data,
     })
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::{hash::Hash, message::{Message, MessageHeader, VersionedMessage}, signature::Signature, transaction::VersionedTransaction};
+    use solana_transaction_status::{InnerInstruction, InnerInstructions, TransactionStatusMeta, TransactionTokenBalance, VersionedTransactionWithStatusMeta};
+    use crate::trade::Side;
+
+    const WSOL: &str = "So11111111111111111111111111111111111111112";
+    const TOKEN_PROGRAM_ID: Pubkey = solana_sdk::pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+    const RAYDIUM_AUTHORITY: Pubkey = solana_sdk::pubkey!("5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1");
+
+    /// One hop of a route: what the owner paid into the pool and got out of it, and the
+    /// pool vault balances after the swap.
+    struct Hop {
+        mint_in: String,
+        mint_out: String,
+        amount_in: u64,
+        amount_out: u64,
+        reserve_in: u64,
+        reserve_out: u64,
+    }
+
+    fn hop(mint_in: &str, mint_out: &str, amount_in: u64, amount_out: u64) -> Hop {
+        Hop {
+            mint_in: mint_in.to_owned(),
+            mint_out: mint_out.to_owned(),
+            amount_in,
+            amount_out,
+            reserve_in: amount_in * 100,
+            reserve_out: amount_out * 100,
+        }
+    }
+
+    /// Assembles a legacy transaction signed by its first account, with inner
+    /// instructions attached to the top-level instruction they were invoked by.
+    struct TxBuilder {
+        keys: Vec<Pubkey>,
+        instructions: Vec<CompiledInstruction>,
+        inner: Vec<InnerInstructions>,
+        balances: Vec<TransactionTokenBalance>,
+    }
+
+    impl TxBuilder {
+        fn new(signer: Pubkey) -> Self {
+            Self { keys: vec![signer], instructions: vec![], inner: vec![], balances: vec![] }
+        }
+
+        fn index(&mut self, key: Pubkey) -> u8 {
+            match self.keys.iter().position(|k| *k == key) {
+                Some(index) => index as u8,
+                None => {
+                    self.keys.push(key);
+                    (self.keys.len() - 1) as u8
+                }
+            }
+        }
+
+        fn compile(&mut self, program: Pubkey, accounts: &[Pubkey], data: Vec<u8>) -> CompiledInstruction {
+            CompiledInstruction {
+                program_id_index: self.index(program),
+                accounts: accounts.iter().map(|key| self.index(*key)).collect(),
+                data,
+            }
+        }
+
+        /// Adds a top-level instruction and returns its index.
+        fn instruction(&mut self, program: Pubkey, accounts: &[Pubkey], data: Vec<u8>) -> usize {
+            let instruction = self.compile(program, accounts, data);
+            self.instructions.push(instruction);
+            self.instructions.len() - 1
+        }
+
+        fn inner(&mut self, outer: usize, stack_height: u32, program: Pubkey, accounts: &[Pubkey], data: Vec<u8>) {
+            let instruction = InnerInstruction { instruction: self.compile(program, accounts, data), stack_height: Some(stack_height) };
+            match self.inner.iter_mut().find(|inner| inner.index as usize == outer) {
+                Some(inner) => inner.instructions.push(instruction),
+                None => self.inner.push(InnerInstructions { index: outer as u8, instructions: vec![instruction] }),
+            }
+        }
+
+        fn transfer(&mut self, outer: usize, stack_height: u32, source: Pubkey, destination: Pubkey, authority: Pubkey, amount: u64) {
+            let mut data = vec![3];
+            data.extend(amount.to_le_bytes());
+            self.inner(outer, stack_height, TOKEN_PROGRAM_ID, &[source, destination, authority], data);
+        }
+
+        fn balance(&mut self, account: Pubkey, mint: &str, amount: u64) {
+            let account_index = self.index(account);
+            self.balances.push(TransactionTokenBalance {
+                account_index,
+                mint: mint.to_owned(),
+                ui_token_amount: serde_json::from_value(serde_json::json!({
+                    "uiAmount": null,
+                    "decimals": 6,
+                    "amount": amount.to_string(),
+                    "uiAmountString": amount.to_string(),
+                })).unwrap(),
+                owner: RAYDIUM_AUTHORITY.to_string(),
+                program_id: TOKEN_PROGRAM_ID.to_string(),
+            });
+        }
+
+        /// Adds a top-level AMM v4 `SwapBaseIn` with its two token transfers, and
+        /// returns the pool.
+        fn swap(&mut self, owner: Pubkey, hop: &Hop) -> Pubkey {
+            let outer = self.instructions.len();
+            self.swap_at(outer, 1, owner, hop)
+        }
+
+        /// Adds a `SwapBaseIn` at `stack_height` of the top-level instruction `outer`
+        /// (the swap itself at height 1), with the transfers it makes one level deeper.
+        fn swap_at(&mut self, outer: usize, stack_height: u32, owner: Pubkey, hop: &Hop) -> Pubkey {
+            let [pool, vault_in, vault_out, user_source, user_destination] = [(); 5].map(|_| Pubkey::new_unique());
+            let mut accounts = vec![TOKEN_PROGRAM_ID, pool, RAYDIUM_AUTHORITY];
+            accounts.extend([(); 12].map(|_| Pubkey::new_unique()));
+            accounts.extend([user_source, user_destination, owner]);
+            let mut data = vec![9];
+            data.extend(hop.amount_in.to_le_bytes());
+            data.extend(0u64.to_le_bytes());
+            match stack_height {
+                1 => _ = self.instruction(RAYDIUM_AMM_V4_PROGRAM_ID, &accounts, data),
+                _ => self.inner(outer, stack_height, RAYDIUM_AMM_V4_PROGRAM_ID, &accounts, data),
+            }
+            self.transfer(outer, stack_height + 1, user_source, vault_in, owner, hop.amount_in);
+            self.transfer(outer, stack_height + 1, vault_out, user_destination, RAYDIUM_AUTHORITY, hop.amount_out);
+            self.balance(vault_in, &hop.mint_in, hop.reserve_in);
+            self.balance(vault_out, &hop.mint_out, hop.reserve_out);
+            pool
+        }
+
+        fn build(self) -> TransactionWithStatusMeta {
+            let message = Message {
+                header: MessageHeader { num_required_signatures: 1, num_readonly_signed_accounts: 0, num_readonly_unsigned_accounts: 0 },
+                account_keys: self.keys,
+                recent_blockhash: Hash::default(),
+                instructions: self.instructions,
+            };
+            TransactionWithStatusMeta::Complete(VersionedTransactionWithStatusMeta {
+                transaction: VersionedTransaction {
+                    signatures: vec![Signature::new_unique()],
+                    message: VersionedMessage::Legacy(message),
+                },
+                meta: TransactionStatusMeta {
+                    inner_instructions: Some(self.inner),
+                    post_token_balances: Some(self.balances),
+                    ..Default::default()
+                },
+            })
+        }
+    }
+
+    #[test]
+    fn decodes_a_single_hop_buy() {
+        let (owner, mint) = (Pubkey::new_unique(), Pubkey::new_unique().to_string());
+        let mut builder = TxBuilder::new(owner);
+        let pool = builder.swap(owner, &hop(WSOL, &mint, 1_000_000, 5_000));
+        let trades = decode_raydium_txn(&builder.build(), 7).unwrap();
+
+        assert_eq!(trades.len(), 1);
+        let trade = &trades[0];
+        assert_eq!((trade.side, trade.wallet.as_str(), trade.pool.as_str(), trade.mint.as_str()), (Side::Buy, owner.to_string().as_str(), pool.to_string().as_str(), mint.as_str()));
+        assert_eq!((trade.sol_amount, trade.token_amount), (1_000_000, 5_000));
+        assert_eq!((trade.sol_reserve, trade.token_reserve), (100_000_000, 500_000));
+        assert_eq!(trade.slot, 7);
+    }
+
+    #[test]
+    fn splits_a_route_through_sol_into_a_sell_and_a_buy() {
+        let owner = Pubkey::new_unique();
+        let (mint_a, mint_b) = (Pubkey::new_unique().to_string(), Pubkey::new_unique().to_string());
+        let mut builder = TxBuilder::new(owner);
+        builder.swap(owner, &hop(&mint_a, WSOL, 8_000, 2_000_000));
+        builder.swap(owner, &hop(WSOL, &mint_b, 2_000_000, 3_000));
+        let trades = decode_raydium_txn(&builder.build(), 7).unwrap();
+
+        assert_eq!(trades.len(), 2);
+        assert_eq!((trades[0].side, trades[0].mint.as_str()), (Side::Sell, mint_a.as_str()));
+        assert_eq!((trades[0].sol_amount, trades[0].token_amount), (2_000_000, 8_000));
+        assert_eq!((trades[1].side, trades[1].mint.as_str()), (Side::Buy, mint_b.as_str()));
+        assert_eq!((trades[1].sol_amount, trades[1].token_amount), (2_000_000, 3_000));
+    }
+
+    #[test]
+    fn collapses_a_route_through_another_token_into_one_trade() {
+        let owner = Pubkey::new_unique();
+        let (usdc, mint) = (Pubkey::new_unique().to_string(), Pubkey::new_unique().to_string());
+        let mut builder = TxBuilder::new(owner);
+        builder.swap(owner, &hop(WSOL, &usdc, 1_000_000, 150_000));
+        let last_pool = builder.swap(owner, &hop(&usdc, &mint, 150_000, 9_000));
+        let trades = decode_raydium_txn(&builder.build(), 7).unwrap();
+
+        // Priced on the last pool, the one holding the bought token
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].side, trades[0].mint.as_str(), trades[0].pool.as_str()), (Side::Buy, mint.as_str(), last_pool.to_string().as_str()));
+        assert_eq!((trades[0].sol_amount, trades[0].token_amount), (1_000_000, 9_000));
+        assert_eq!((trades[0].sol_reserve, trades[0].token_reserve), (15_000_000, 900_000));
+    }
+
+    #[test]
+    fn skips_a_swap_that_cannot_be_reconstructed() {
+        let owner = Pubkey::new_unique();
+        let [mint_a, mint_b, mint_c] = [(); 3].map(|_| Pubkey::new_unique().to_string());
+        let mut builder = TxBuilder::new(owner);
+        builder.swap(owner, &hop(WSOL, &mint_a, 1_000_000, 5_000));
+        builder.swap(owner, &hop(&mint_b, WSOL, 8_000, 2_000_000));
+        // A swap without its token transfers
+        let accounts: Vec<Pubkey> = (0..18).map(|_| Pubkey::new_unique()).collect();
+        let mut data = vec![9];
+        data.extend([0u8; 16]);
+        builder.instruction(RAYDIUM_AMM_V4_PROGRAM_ID, &accounts, data);
+        builder.swap(owner, &hop(WSOL, &mint_c, 2_000_000, 3_000));
+        let trades = decode_raydium_txn(&builder.build(), 7).unwrap();
+
+        // The routes on either side of the gap may be missing a hop, the first one stands
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].side, trades[0].mint.as_str()), (Side::Buy, mint_a.as_str()));
+    }
+
+    #[test]
+    fn flattens_inner_instructions_under_their_parent() {
+        let (owner, mint) = (Pubkey::new_unique(), Pubkey::new_unique().to_string());
+        let (aggregator, fee_account) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut builder = TxBuilder::new(owner);
+        // An aggregator route invoking a swap, which invokes its transfers, then
+        // charging its fee; followed by an unrelated top-level instruction
+        let route = builder.instruction(aggregator, &[owner], vec![1]);
+        builder.swap_at(route, 2, owner, &hop(WSOL, &mint, 1_000_000, 5_000));
+        builder.transfer(route, 2, Pubkey::new_unique(), fee_account, owner, 1_000);
+        builder.instruction(aggregator, &[owner], vec![2]);
+        let tx = builder.build();
+
+        let ixs = flatten_transaction_response(&tx).unwrap();
+        let shape: Vec<(usize, u32)> = ixs.iter().map(|ix| (ix.outer_index, ix.stack_height)).collect();
+        assert_eq!(shape, vec![(0, 1), (0, 2), (0, 3), (0, 3), (0, 2), (1, 1)]);
+        assert_eq!(ixs[0].instruction.program_id, aggregator);
+        assert_eq!(ixs[1].instruction.program_id, RAYDIUM_AMM_V4_PROGRAM_ID);
+
+        assert_eq!(cpi_children(&ixs, 0).len(), 4);
+        let swap_children = cpi_children(&ixs, 1);
+        assert_eq!(swap_children.len(), 2);
+        assert!(swap_children.iter().all(|child| child.instruction.program_id == TOKEN_PROGRAM_ID));
+        assert!(cpi_children(&ixs, 2).is_empty());
+        assert!(cpi_children(&ixs, 5).is_empty());
+        assert!(cpi_children(&ixs, 6).is_empty());
+
+        // The routed swap is paired with its own transfers, not the aggregator fee
+        let trades = decode_raydium_txn(&tx, 7).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].side, trades[0].mint.as_str()), (Side::Buy, mint.as_str()));
+        assert_eq!((trades[0].sol_amount, trades[0].token_amount), (1_000_000, 5_000));
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use solana_sdk::pubkey::Pubkey;
+use crate::trade::Dex;
+
+/// Latest known state of a SOL pool: a Raydium AMM or a Pump.fun bonding curve.
+#[derive(Debug, Clone)]
+pub struct PoolState {
+    pub dex: Dex,
+    /// The token traded against SOL
+    pub mint: String,
+    pub sol_reserve: u64,
+    pub token_reserve: u64,
+    /// Pump.fun only: tokens left to sell on the curve
+    pub real_token_reserve: Option<u64>,
+    pub slot: u64,
+    /// Last trade seen on the pool; account updates keep it
+    pub signature: Option<String>,
+    /// Raydium only: the SOL and token vaults, whose balances are the reserves
+    pub vaults: Option<(Pubkey, Pubkey)>,
+}
+
+/// Process-wide pool states, fed by every decoded trade and by account updates of
+/// the watched pools. Keyed by AMM id or bonding curve.
+pub struct PoolCache {
+    pools: RwLock<BTreeMap<String, PoolState>>,
+    /// Raydium vault -> (AMM id, is the SOL vault)
+    vaults: RwLock<BTreeMap<Pubkey, (String, bool)>>,
+    /// Bumped whenever a pool is added, so subscriptions know to refresh
+    version: AtomicU64,
+}
+
+pub static POOLS: PoolCache = PoolCache {
+    pools: RwLock::new(BTreeMap::new()),
+    vaults: RwLock::new(BTreeMap::new()),
+    version: AtomicU64::new(0),
+};
+
+/// Bonding curve account: discriminator, then virtual token, virtual SOL, real token
+/// and real SOL reserves.
+const CURVE_VIRTUAL_TOKEN_OFFSET: usize = 8;
+const CURVE_VIRTUAL_SOL_OFFSET: usize = 16;
+const CURVE_REAL_TOKEN_OFFSET: usize = 24;
+/// SPL token account amount
+const TOKEN_ACCOUNT_AMOUNT_OFFSET: usize = 64;
+
+impl PoolCache {
+    fn read(&self) -> RwLockReadGuard<'_, BTreeMap<String, PoolState>> {
+        match self.pools.read() {
+            Ok(pools) => pools,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, BTreeMap<String, PoolState>> {
+        match self.pools.write() {
+            Ok(pools) => pools,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Replaces the pool's state unless a newer slot is already known (backfilled
+    /// history is decoded alongside live trades).
+    pub fn update(&self, pool: &str, state: PoolState) {
+        if let Some((sol_vault, token_vault)) = state.vaults {
+            let mut vaults = match self.vaults.write() {
+                Ok(vaults) => vaults,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            vaults.insert(sol_vault, (pool.to_owned(), true));
+            vaults.insert(token_vault, (pool.to_owned(), false));
+        }
+        let mut pools = self.write();
+        match pools.get_mut(pool) {
+            Some(current) if current.slot > state.slot => {}
+            Some(current) => *current = state,
+            None => {
+                pools.insert(pool.to_owned(), state);
+                self.version.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Applies an account update of a watched bonding curve or Raydium vault.
+    /// Returns false if the account is not one the cache knows about.
+    pub fn apply_account(&self, pubkey: &Pubkey, data: &[u8], slot: u64) -> bool {
+        let vault = match self.vaults.read() {
+            Ok(vaults) => vaults.get(pubkey).cloned(),
+            Err(poisoned) => poisoned.into_inner().get(pubkey).cloned(),
+        };
+        let mut pools = self.write();
+        if let Some((pool, is_sol_vault)) = vault {
+            let (Some(state), Some(amount)) = (pools.get_mut(&pool), read_u64(data, TOKEN_ACCOUNT_AMOUNT_OFFSET)) else {
+                return false;
+            };
+            if state.slot <= slot {
+                match is_sol_vault {
+                    true => state.sol_reserve = amount,
+                    false => state.token_reserve = amount,
+                }
+                state.slot = slot;
+            }
+            return true;
+        }
+
+        let Some(state) = pools.get_mut(&pubkey.to_string()) else {
+            return false;
+        };
+        let reserves = (
+            read_u64(data, CURVE_VIRTUAL_SOL_OFFSET),
+            read_u64(data, CURVE_VIRTUAL_TOKEN_OFFSET),
+            read_u64(data, CURVE_REAL_TOKEN_OFFSET),
+        );
+        if let (Some(sol_reserve), Some(token_reserve), Some(real_token_reserve)) = reserves {
+            if state.dex == Dex::Pumpfun && state.slot <= slot {
+                state.sol_reserve = sol_reserve;
+                state.token_reserve = token_reserve;
+                state.real_token_reserve = Some(real_token_reserve);
+                state.slot = slot;
+            }
+        }
+        true
+    }
+
+    pub fn get(&self, pool: &str) -> Option<PoolState> {
+        self.read().get(pool).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    /// Accounts to subscribe to for the `limit` most recently traded pools of `dex`:
+    /// bonding curves for Pump.fun, vaults for Raydium.
+    pub fn watched_accounts(&self, dex: Dex, limit: usize) -> Vec<String> {
+        let mut pools: Vec<_> = self.read().iter()
+            .filter(|(_, state)| state.dex == dex)
+            .map(|(pool, state)| (pool.clone(), state.slot, state.vaults))
+            .collect();
+        pools.sort_by_key(|(_, slot, _)| std::cmp::Reverse(*slot));
+        pools.into_iter()
+            .take(limit)
+            .flat_map(|(pool, _, vaults)| match vaults {
+                Some((sol_vault, token_vault)) => vec![sol_vault.to_string(), token_vault.to_string()],
+                None => vec![pool],
+            })
+            .collect()
+    }
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(offset..offset + 8)?.try_into().ok()?))
+}
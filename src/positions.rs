@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use crate::trade::{Dex, Side, TradeEvent};
+
+/// Applied trades are remembered for this many slots past the latest one, which
+/// covers any overlap between a backfill and the live feed.
+const APPLIED_RETENTION_SLOTS: u64 = 10_000;
+
+/// A target wallet's holding of one mint, rebuilt from its trades.
+#[derive(Debug, Clone)]
+pub struct Position {
+    pub dex: Dex,
+    pub pool: String,
+    pub token_amount: u64,
+    pub sol_spent: u64,
+    pub sol_received: u64,
+    pub buys: u32,
+    pub sells: u32,
+    pub opened_slot: u64,
+    pub last_slot: u64,
+}
+
+/// Track record of a target wallet over its closed positions.
+#[derive(Debug, Default, Clone)]
+pub struct WalletScore {
+    pub trades: u64,
+    pub closed_positions: u64,
+    pub winning_positions: u64,
+    pub realized_pnl: i64,
+}
+
+/// Open positions and scores of every target wallet. Fed by the RPC backfill and by
+/// live trades, so sells can be mirrored against what the target actually holds.
+#[derive(Debug, Default)]
+pub struct PositionBook {
+    positions: HashMap<String, HashMap<String, Position>>,
+    scores: HashMap<String, WalletScore>,
+    /// (signature, mint) of the applied trades, and their slot
+    applied: HashMap<(String, String), u64>,
+    latest_slot: u64,
+    pruned_at: u64,
+    /// Wallets being backfilled, and their live trades held until it is done
+    backfilling: HashMap<String, Vec<TradeEvent>>,
+}
+
+impl PositionBook {
+    /// Holds the live trades of `wallet` until `finish_backfill`, so they are applied
+    /// after its history.
+    pub fn start_backfill(&mut self, wallet: &str) {
+        self.backfilling.entry(wallet.to_owned()).or_default();
+    }
+
+    /// Applies the live trades of `wallet` held during its backfill.
+    pub fn finish_backfill(&mut self, wallet: &str) {
+        for trade in self.backfilling.remove(wallet).unwrap_or_default() {
+            self.apply(&trade);
+        }
+    }
+
+    /// Applies a trade from the live feed, or holds it while its wallet is being
+    /// backfilled.
+    pub fn apply_live(&mut self, trade: &TradeEvent) {
+        match self.backfilling.get_mut(&trade.wallet) {
+            Some(held) => held.push(trade.clone()),
+            None => {
+                self.apply(trade);
+            }
+        }
+    }
+
+    /// Applies a trade to the wallet's position. Returns false if the same trade was
+    /// already applied (backfill and live feed can overlap).
+    pub fn apply(&mut self, trade: &TradeEvent) -> bool {
+        let key = (trade.signature.clone(), trade.mint.clone());
+        if self.applied.contains_key(&key) {
+            return false;
+        }
+        self.applied.insert(key, trade.slot);
+        self.latest_slot = self.latest_slot.max(trade.slot);
+        if self.latest_slot >= self.pruned_at + APPLIED_RETENTION_SLOTS {
+            let oldest = self.latest_slot - APPLIED_RETENTION_SLOTS;
+            self.applied.retain(|_, slot| *slot >= oldest);
+            self.pruned_at = self.latest_slot;
+        }
+        let score = self.scores.entry(trade.wallet.clone()).or_default();
+        score.trades += 1;
+
+        let positions = self.positions.entry(trade.wallet.clone()).or_default();
+        let position = positions.entry(trade.mint.clone()).or_insert_with(|| Position {
+            dex: trade.dex,
+            pool: trade.pool.clone(),
+            token_amount: 0,
+            sol_spent: 0,
+            sol_received: 0,
+            buys: 0,
+            sells: 0,
+            opened_slot: trade.slot,
+            last_slot: trade.slot,
+        });
+        position.last_slot = position.last_slot.max(trade.slot);
+        match trade.side {
+            Side::Buy => {
+                position.token_amount += trade.token_amount;
+                position.sol_spent += trade.sol_amount;
+                position.buys += 1;
+            }
+            Side::Sell => {
+                // Sells of tokens bought before the backfill window just close the position
+                position.token_amount = position.token_amount.saturating_sub(trade.token_amount);
+                position.sol_received += trade.sol_amount;
+                position.sells += 1;
+            }
+        }
+
+        if position.token_amount == 0 {
+            if let Some(closed) = positions.remove(&trade.mint) {
+                let pnl = closed.sol_received as i64 - closed.sol_spent as i64;
+                score.closed_positions += 1;
+                score.realized_pnl += pnl;
+                if pnl > 0 {
+                    score.winning_positions += 1;
+                }
+            }
+        }
+        true
+    }
+
+    /// Undoes a trade that turned out not to have happened (dead or skipped slot).
+    pub fn revert(&mut self, trade: &TradeEvent) {
+        if let Some(held) = self.backfilling.get_mut(&trade.wallet) {
+            held.retain(|held| held.signature != trade.signature || held.mint != trade.mint);
+        }
+        if self.applied.remove(&(trade.signature.clone(), trade.mint.clone())).is_none() {
+            return;
+        }
+        if let Some(position) = self.positions.get_mut(&trade.wallet).and_then(|positions| positions.get_mut(&trade.mint)) {
+            match trade.side {
+                Side::Buy => {
+                    position.token_amount = position.token_amount.saturating_sub(trade.token_amount);
+                    position.sol_spent = position.sol_spent.saturating_sub(trade.sol_amount);
+                    position.buys = position.buys.saturating_sub(1);
+                }
+                Side::Sell => {
+                    position.token_amount += trade.token_amount;
+                    position.sol_received = position.sol_received.saturating_sub(trade.sol_amount);
+                    position.sells = position.sells.saturating_sub(1);
+                }
+            }
+        }
+    }
+
+    pub fn position(&self, wallet: &str, mint: &str) -> Option<&Position> {
+        self.positions.get(wallet).and_then(|positions| positions.get(mint))
+    }
+
+    pub fn open_positions(&self, wallet: &str) -> Vec<(&String, &Position)> {
+        self.positions.get(wallet).map(|positions| positions.iter().collect()).unwrap_or_default()
+    }
+
+    pub fn score(&self, wallet: &str) -> WalletScore {
+        self.scores.get(wallet).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(signature: &str, slot: u64, side: Side, token_amount: u64) -> TradeEvent {
+        TradeEvent {
+            dex: Dex::Pumpfun,
+            signature: signature.to_owned(),
+            slot,
+            wallet: "target".to_owned(),
+            pool: "curve".to_owned(),
+            mint: "mint".to_owned(),
+            side,
+            sol_amount: token_amount / 1000,
+            token_amount,
+            sol_reserve: 0,
+            token_reserve: 0,
+            price_impact: 0.0,
+        }
+    }
+
+    #[test]
+    fn live_trades_wait_for_the_backfill() {
+        let mut book = PositionBook::default();
+        book.start_backfill("target");
+        book.apply_live(&trade("live-sell", 20, Side::Sell, 400));
+        assert!(book.position("target", "mint").is_none());
+
+        // The backfill also finds the live trade, which is only applied once
+        book.apply(&trade("old-buy", 10, Side::Buy, 1000));
+        book.apply(&trade("live-sell", 20, Side::Sell, 400));
+        book.finish_backfill("target");
+        assert_eq!(book.position("target", "mint").map(|position| position.token_amount), Some(600));
+        assert_eq!(book.score("target").trades, 2);
+    }
+
+    #[test]
+    fn reverted_trades_are_not_held() {
+        let mut book = PositionBook::default();
+        book.start_backfill("target");
+        book.apply_live(&trade("dropped-buy", 20, Side::Buy, 1000));
+        book.revert(&trade("dropped-buy", 20, Side::Buy, 1000));
+        book.finish_backfill("target");
+        assert!(book.position("target", "mint").is_none());
+    }
+
+    #[test]
+    fn applied_trades_expire_by_slot() {
+        let mut book = PositionBook::default();
+        book.apply(&trade("first", 1, Side::Buy, 1000));
+        assert!(!book.apply(&trade("first", 1, Side::Buy, 1000)));
+        book.apply(&trade("later", 2 + APPLIED_RETENTION_SLOTS, Side::Buy, 1000));
+        assert_eq!(book.applied.len(), 1);
+    }
+}
@@ -0,0 +1,2 @@
+pub mod pumpfun;
+pub mod raydium;
@@ -0,0 +1,161 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::pools::PoolState;
+
+/// Bonding curve state of a mint, as reported by its latest `CPILog` or account
+/// update. Prices are set by the virtual reserves; the real token reserves cap what
+/// can be bought.
+#[derive(Debug, Clone, Copy)]
+pub struct BondingCurve {
+    pub virtual_sol_reserves: u64,
+    pub virtual_token_reserves: u64,
+    pub real_token_reserves: u64,
+}
+
+impl From<&PoolState> for BondingCurve {
+    /// Without a known real token reserve, buys are not capped.
+    fn from(state: &PoolState) -> Self {
+        Self {
+            virtual_sol_reserves: state.sol_reserve,
+            virtual_token_reserves: state.token_reserve,
+            real_token_reserves: state.real_token_reserve.unwrap_or(u64::MAX),
+        }
+    }
+}
+
+/// Arguments of a `Buy`: the exact token amount, and the most SOL (fee included) the
+/// program may charge for it.
+#[derive(Debug, Clone, Copy)]
+pub struct PumpBuyQuote {
+    pub token_amount: u64,
+    pub sol_cost: u64,
+    pub fee: u64,
+    pub max_sol_cost: u64,
+}
+
+/// Arguments of a `Sell`: the exact token amount, and the least SOL (fee deducted)
+/// the program must pay out for it.
+#[derive(Debug, Clone, Copy)]
+pub struct PumpSellQuote {
+    pub token_amount: u64,
+    pub sol_output: u64,
+    pub fee: u64,
+    pub min_sol_output: u64,
+}
+
+impl BondingCurve {
+    /// SOL the program charges for `token_amount`, before the fee. Rounded up by one
+    /// lamport like the program does.
+    pub fn buy_cost(&self, token_amount: u64) -> Option<u64> {
+        if token_amount >= self.virtual_token_reserves || token_amount > self.real_token_reserves {
+            return None;
+        }
+        let cost = token_amount as u128 * self.virtual_sol_reserves as u128
+            / (self.virtual_token_reserves - token_amount) as u128
+            + 1;
+        u64::try_from(cost).ok()
+    }
+
+    /// Largest token amount whose cost plus fee fits in `sol_budget`, priced with
+    /// `buy_cost`. The slippage tolerance in percent widens `max_sol_cost`.
+    pub fn buy_quote(&self, sol_budget: u64, fee_bps: u64, slippage_tolerance: f64) -> Option<PumpBuyQuote> {
+        let sol_in = sol_budget as u128 * 10_000 / (10_000 + fee_bps as u128);
+        let k = self.virtual_sol_reserves as u128 * self.virtual_token_reserves as u128;
+        let remaining = k / (self.virtual_sol_reserves as u128 + sol_in) + 1;
+        let mut token_amount = (self.virtual_token_reserves as u128).checked_sub(remaining)? as u64;
+        token_amount = token_amount.min(self.real_token_reserves);
+        if token_amount == 0 {
+            return None;
+        }
+        let mut sol_cost = self.buy_cost(token_amount)?;
+        // The +1 lamport can push the cost over the budget by a hair
+        while sol_cost + fee(sol_cost, fee_bps) > sol_budget && token_amount > 1 {
+            token_amount -= 1;
+            sol_cost = self.buy_cost(token_amount)?;
+        }
+        let fee_amount = fee(sol_cost, fee_bps);
+        let tolerance = (slippage_tolerance / 100.0).max(0.0);
+        Some(PumpBuyQuote {
+            token_amount,
+            sol_cost,
+            fee: fee_amount,
+            max_sol_cost: ((sol_cost + fee_amount) as f64 * (1.0 + tolerance)).ceil() as u64,
+        })
+    }
+
+    /// SOL paid out for `token_amount`: the curve output rounded down, minus the fee.
+    pub fn sell_quote(&self, token_amount: u64, fee_bps: u64, slippage_tolerance: f64) -> Option<PumpSellQuote> {
+        if token_amount == 0 {
+            return None;
+        }
+        let gross = token_amount as u128 * self.virtual_sol_reserves as u128
+            / (self.virtual_token_reserves as u128 + token_amount as u128);
+        let gross = u64::try_from(gross).ok()?;
+        let fee_amount = fee(gross, fee_bps);
+        let sol_output = gross - fee_amount;
+        let tolerance = (slippage_tolerance / 100.0).clamp(0.0, 1.0);
+        Some(PumpSellQuote {
+            token_amount,
+            sol_output,
+            fee: fee_amount,
+            min_sol_output: (sol_output as f64 * (1.0 - tolerance)).floor() as u64,
+        })
+    }
+}
+
+/// Fee on a SOL amount, rounded up in the program's favour.
+fn fee(amount: u64, fee_bps: u64) -> u64 {
+    (amount as u128 * fee_bps as u128).div_ceil(10_000) as u64
+}
+
+/// Trade fee of the global account, set from the config at startup and then from
+/// any `SetParams` seen on chain.
+static FEE_BASIS_POINTS: AtomicU64 = AtomicU64::new(100);
+
+pub fn fee_basis_points() -> u64 {
+    FEE_BASIS_POINTS.load(Ordering::Relaxed)
+}
+
+pub fn set_fee_basis_points(fee_basis_points: u64) {
+    FEE_BASIS_POINTS.store(fee_basis_points, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Virtual and real reserves of a freshly created curve
+    const CURVE: BondingCurve = BondingCurve {
+        virtual_sol_reserves: 30_000_000_000,
+        virtual_token_reserves: 1_073_000_000_000_000,
+        real_token_reserves: 793_100_000_000_000,
+    };
+
+    #[test]
+    fn buy_fits_the_budget_fee_included() {
+        let quote = CURVE.buy_quote(1_000_000_000, 100, 5.0).unwrap();
+        assert_eq!(quote.token_amount, 34_281_150_129_545);
+        assert_eq!(quote.sol_cost, 990_099_009);
+        assert_eq!(quote.fee, 9_900_991);
+        assert_eq!(quote.sol_cost + quote.fee, 1_000_000_000);
+        assert_eq!(quote.max_sol_cost, 1_050_000_000);
+    }
+
+    #[test]
+    fn buys_are_capped_by_the_real_reserves() {
+        assert_eq!(CURVE.buy_cost(CURVE.real_token_reserves), Some(85_005_359_057));
+        assert_eq!(CURVE.buy_cost(CURVE.real_token_reserves + 1), None);
+
+        // A budget far beyond the curve only gets what is left on it
+        let quote = CURVE.buy_quote(1_000_000_000_000, 100, 0.0).unwrap();
+        assert_eq!(quote.token_amount, CURVE.real_token_reserves);
+    }
+
+    #[test]
+    fn sell_rounds_down_and_deducts_the_fee() {
+        let quote = CURVE.sell_quote(34_281_150_129_545, 100, 5.0).unwrap();
+        assert_eq!(quote.fee, 9_287_926);
+        assert_eq!(quote.sol_output, 919_504_642);
+        assert_eq!(quote.min_sol_output, 873_529_409);
+        assert!(CURVE.sell_quote(0, 100, 5.0).is_none());
+    }
+}
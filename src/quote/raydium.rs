@@ -0,0 +1,121 @@
+/// Swap fee of an AMM v4 pool, taken from the input amount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RaydiumFees {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl Default for RaydiumFees {
+    /// 0.25%, the fee of every standard AMM v4 pool
+    fn default() -> Self {
+        Self { numerator: 25, denominator: 10_000 }
+    }
+}
+
+/// Result of a constant-product swap, rounded the way the AMM v4 program rounds.
+#[derive(Debug, Clone, Copy)]
+pub struct RaydiumQuote {
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub fee: u64,
+    /// Execution price against the spot price, fee excluded
+    pub price_impact: f64,
+}
+
+/// Quotes `SwapBaseIn`: the fee is rounded up and taken from `amount_in`, the output
+/// is rounded down. Reserves are the pool vault balances before the swap.
+pub fn quote_exact_in(amount_in: u64, reserve_in: u64, reserve_out: u64, fees: RaydiumFees) -> Option<RaydiumQuote> {
+    let fee = ceil_div(amount_in as u128 * fees.numerator as u128, fees.denominator as u128)?;
+    let amount_in_after_fee = (amount_in as u128).checked_sub(fee)?;
+    let amount_out = reserve_out as u128 * amount_in_after_fee / (reserve_in as u128 + amount_in_after_fee).max(1);
+    Some(RaydiumQuote {
+        amount_in,
+        amount_out: u64::try_from(amount_out).ok()?,
+        fee: fee as u64,
+        price_impact: price_impact(amount_in_after_fee, amount_out, reserve_in, reserve_out),
+    })
+}
+
+/// Quotes `SwapBaseOut`: the input needed for `amount_out` is rounded up, then grossed
+/// up by the fee, rounded up again.
+pub fn quote_exact_out(amount_out: u64, reserve_in: u64, reserve_out: u64, fees: RaydiumFees) -> Option<RaydiumQuote> {
+    let remaining_out = (reserve_out as u128).checked_sub(amount_out as u128).filter(|remaining| *remaining > 0)?;
+    let amount_in_without_fee = ceil_div(reserve_in as u128 * amount_out as u128, remaining_out)?;
+    let amount_in = ceil_div(
+        amount_in_without_fee * fees.denominator as u128,
+        (fees.denominator as u128).checked_sub(fees.numerator as u128)?,
+    )?;
+    Some(RaydiumQuote {
+        amount_in: u64::try_from(amount_in).ok()?,
+        amount_out,
+        fee: (amount_in - amount_in_without_fee) as u64,
+        price_impact: price_impact(amount_in_without_fee, amount_out as u128, reserve_in, reserve_out),
+    })
+}
+
+/// `minimum_out` of a swap given the quoted output and a slippage tolerance in percent.
+pub fn minimum_out(amount_out: u64, slippage_tolerance: f64) -> u64 {
+    let tolerance = (slippage_tolerance / 100.0).clamp(0.0, 1.0);
+    (amount_out as f64 * (1.0 - tolerance)).floor() as u64
+}
+
+fn price_impact(amount_in_after_fee: u128, amount_out: u128, reserve_in: u64, reserve_out: u64) -> f64 {
+    if amount_in_after_fee == 0 || reserve_in == 0 || reserve_out == 0 {
+        return 0.0;
+    }
+    let spot_out = amount_in_after_fee as f64 * reserve_out as f64 / reserve_in as f64;
+    1.0 - amount_out as f64 / spot_out
+}
+
+fn ceil_div(numerator: u128, denominator: u128) -> Option<u128> {
+    if denominator == 0 {
+        return None;
+    }
+    Some(numerator.div_ceil(denominator))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESERVE_IN: u64 = 1_000_000_000;
+    const RESERVE_OUT: u64 = 2_000_000_000;
+
+    #[test]
+    fn exact_in_rounds_the_fee_up_and_the_output_down() {
+        let quote = quote_exact_in(1_000_000, RESERVE_IN, RESERVE_OUT, RaydiumFees::default()).unwrap();
+        assert_eq!(quote.fee, 2_500);
+        // 2e9 * 997_500 / 1_000_997_500 = 1_993_011.9
+        assert_eq!(quote.amount_out, 1_993_011);
+
+        // 401 * 0.25% = 1.0025, charged as 2
+        assert_eq!(quote_exact_in(401, RESERVE_IN, RESERVE_OUT, RaydiumFees::default()).unwrap().fee, 2);
+        // Dust is eaten by the fee
+        let dust = quote_exact_in(1, RESERVE_IN, RESERVE_OUT, RaydiumFees::default()).unwrap();
+        assert_eq!((dust.fee, dust.amount_out), (1, 0));
+    }
+
+    #[test]
+    fn exact_out_rounds_the_input_up_twice() {
+        let quote = quote_exact_out(1_000_000, RESERVE_IN, RESERVE_OUT, RaydiumFees::default()).unwrap();
+        // 1e9 * 1e6 / 1_999_000_000 = 500_250.1 -> 500_251, / 0.9975 = 501_504.3 -> 501_505
+        assert_eq!(quote.amount_in, 501_505);
+        assert_eq!(quote.fee, 1_254);
+
+        // Swapping the quoted input in gets at least the requested output
+        let back = quote_exact_in(quote.amount_in, RESERVE_IN, RESERVE_OUT, RaydiumFees::default()).unwrap();
+        assert_eq!(back.amount_out, 1_000_001);
+    }
+
+    #[test]
+    fn exact_out_cannot_drain_the_pool() {
+        assert!(quote_exact_out(RESERVE_OUT, RESERVE_IN, RESERVE_OUT, RaydiumFees::default()).is_none());
+        assert!(quote_exact_out(RESERVE_OUT + 1, RESERVE_IN, RESERVE_OUT, RaydiumFees::default()).is_none());
+    }
+
+    #[test]
+    fn minimum_out_applies_the_tolerance() {
+        assert_eq!(minimum_out(1_993_011, 1.0), 1_973_080);
+        assert_eq!(minimum_out(1_993_011, 150.0), 0);
+    }
+}
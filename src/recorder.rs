@@ -0,0 +1,230 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use log::{info, warn};
+use prost::encoding::{self, encode_varint, encoded_len_varint};
+use prost::Message;
+use yellowstone_grpc_proto::prelude::SubscribeUpdate;
+
+pub const RECORDING_EXTENSION: &str = "pb";
+const FRAME_CHANNEL_CAPACITY: usize = 16_384;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One recorded frame: the raw update, the feed it came from and when it arrived.
+/// Frames are written as length-delimited protobuf, back to back.
+#[derive(Clone, PartialEq, Message)]
+pub struct RecordedUpdate {
+    #[prost(uint64, tag = "1")]
+    pub received_at_us: u64,
+    #[prost(string, tag = "2")]
+    pub feed: String,
+    #[prost(message, optional, tag = "3")]
+    pub update: Option<SubscribeUpdate>,
+}
+
+/// Writes every raw `SubscribeUpdate` of a monitor to disk, one file per hour named
+/// `<prefix>-<YYYYmmddHH>.pb`. Frames are encoded on the feed task and written by a
+/// blocking writer task, which flushes every `FLUSH_INTERVAL` and on rotation.
+pub struct UpdateRecorder {
+    frames: SyncSender<Vec<u8>>,
+}
+
+impl UpdateRecorder {
+    pub fn new(dir: &str, prefix: &str) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let (frames, frames_rx) = sync_channel(FRAME_CHANNEL_CAPACITY);
+        let writer = RecordingWriter { dir: PathBuf::from(dir), prefix: prefix.to_owned(), current: None };
+        tokio::task::spawn_blocking(move || writer.run(frames_rx));
+        Ok(Self { frames })
+    }
+
+    /// Queues the update for writing. Frames are dropped rather than holding up the
+    /// feed if the writer falls behind.
+    pub fn record(&self, feed: &str, update: &SubscribeUpdate) {
+        let received_at_us = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as u64).unwrap_or_default();
+        match self.frames.try_send(encode_frame(received_at_us, feed, update)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("Recorder is behind, dropped an update"),
+            Err(TrySendError::Disconnected(_)) => warn!("Recorder writer is gone, dropped an update"),
+        }
+    }
+}
+
+/// Encodes a `RecordedUpdate` frame, length-delimited, straight from the borrowed
+/// update so that recording does not clone it.
+fn encode_frame(received_at_us: u64, feed: &str, update: &SubscribeUpdate) -> Vec<u8> {
+    let feed = feed.to_owned();
+    let len = encoding::uint64::encoded_len(1, &received_at_us)
+        + encoding::string::encoded_len(2, &feed)
+        + encoding::message::encoded_len(3, update);
+    let mut buf = Vec::with_capacity(encoded_len_varint(len as u64) + len);
+    encode_varint(len as u64, &mut buf);
+    encoding::uint64::encode(1, &received_at_us, &mut buf);
+    encoding::string::encode(2, &feed, &mut buf);
+    encoding::message::encode(3, update, &mut buf);
+    buf
+}
+
+/// Owns the recording files, on a blocking thread.
+struct RecordingWriter {
+    dir: PathBuf,
+    prefix: String,
+    current: Option<(String, BufWriter<File>)>,
+}
+
+impl RecordingWriter {
+    /// Writes frames until every recorder handle is dropped, then flushes.
+    fn run(mut self, frames: Receiver<Vec<u8>>) {
+        loop {
+            let result = match frames.recv_timeout(FLUSH_INTERVAL) {
+                Ok(frame) => self.write(&frame),
+                Err(RecvTimeoutError::Timeout) => self.flush(),
+                Err(RecvTimeoutError::Disconnected) => {
+                    if let Err(e) = self.flush() {
+                        warn!("Failed to flush recording: {:#}", e);
+                    }
+                    return;
+                }
+            };
+            if let Err(e) = result {
+                warn!("Failed to record update: {:#}", e);
+            }
+        }
+    }
+
+    fn write(&mut self, frame: &[u8]) -> Result<()> {
+        let hour = Utc::now().format("%Y%m%d%H").to_string();
+        // Rotate to a new file when the hour changes
+        if self.current.as_ref().map(|(current_hour, _)| current_hour != &hour).unwrap_or(true) {
+            self.flush()?;
+            let path = self.dir.join(format!("{}-{}.{}", self.prefix, hour, RECORDING_EXTENSION));
+            let file = OpenOptions::new().create(true).append(true).open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            info!("Recording updates to {}", path.display());
+            self.current = Some((hour, BufWriter::new(file)));
+        }
+        if let Some((_, writer)) = self.current.as_mut() {
+            writer.write_all(frame)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some((_, writer)) = self.current.as_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the frames of a recording, in the order they were written.
+pub struct RecordingReader {
+    reader: BufReader<File>,
+}
+
+impl RecordingReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(Self { reader: BufReader::new(file) })
+    }
+
+    fn read_frame(&mut self) -> Result<Option<RecordedUpdate>> {
+        // The length prefix is a varint of at most 10 bytes
+        let mut len: u64 = 0;
+        for shift in (0..70).step_by(7) {
+            let mut byte = [0u8; 1];
+            if self.reader.read(&mut byte)? == 0 {
+                if shift == 0 {
+                    return Ok(None);
+                }
+                return Err(anyhow::anyhow!("Truncated frame length"));
+            }
+            len |= ((byte[0] & 0x7f) as u64) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut buf = vec![0u8; len as usize];
+        self.reader.read_exact(&mut buf).context("Truncated frame")?;
+        Ok(Some(RecordedUpdate::decode(buf.as_slice())?))
+    }
+}
+
+impl Iterator for RecordingReader {
+    type Item = Result<RecordedUpdate>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+/// Recordings in `dir` whose name starts with `prefix`, in chronological order.
+pub fn list_recordings(dir: &str, prefix: &str) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir))? {
+        let path = entry?.path();
+        let matches = path.extension().is_some_and(|ext| ext == RECORDING_EXTENSION)
+            && path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with(&format!("{}-", prefix)));
+        if matches {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdateSlot};
+
+    fn slot_update(slot: u64) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["slots".to_owned()],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot { slot, ..Default::default() })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn frames_round_trip_through_the_reader() {
+        let path = std::env::temp_dir().join(format!("recorder-round-trip-{}.{}", std::process::id(), RECORDING_EXTENSION));
+        let updates = [slot_update(1), slot_update(300), slot_update(u64::MAX)];
+        let mut file = File::create(&path).unwrap();
+        for (i, update) in updates.iter().enumerate() {
+            file.write_all(&encode_frame(i as u64, "feed", update)).unwrap();
+        }
+        drop(file);
+
+        let frames: Vec<RecordedUpdate> = RecordingReader::open(&path).unwrap().collect::<Result<_>>().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(frames.len(), updates.len());
+        for (i, (frame, update)) in frames.iter().zip(&updates).enumerate() {
+            assert_eq!(frame.received_at_us, i as u64);
+            assert_eq!(frame.feed, "feed");
+            assert_eq!(frame.update.as_ref(), Some(update));
+        }
+    }
+
+    #[test]
+    fn encoded_frame_matches_prost() {
+        let update = slot_update(7);
+        let frame = RecordedUpdate { received_at_us: 123, feed: "feed".to_owned(), update: Some(update.clone()) };
+        assert_eq!(encode_frame(123, "feed", &update), frame.encode_length_delimited_to_vec());
+    }
+
+    #[test]
+    fn truncated_frame_is_an_error() {
+        let path = std::env::temp_dir().join(format!("recorder-truncated-{}.{}", std::process::id(), RECORDING_EXTENSION));
+        let frame = encode_frame(1, "feed", &slot_update(1));
+        std::fs::write(&path, &frame[..frame.len() - 1]).unwrap();
+
+        let mut reader = RecordingReader::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(reader.next().unwrap().is_err());
+    }
+}
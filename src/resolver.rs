@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use anyhow::{anyhow, Result};
+use log::info;
+use solana_sdk::{instruction::AccountMeta, pubkey, pubkey::Pubkey};
+use crate::config::Config;
+use crate::quote::raydium::RaydiumFees;
+use crate::rpc::{ChainRpc, RpcClient};
+
+const RAYDIUM_AMM_AUTHORITY: Pubkey = pubkey!("5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1");
+const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+
+/// AMM v4 `AmmInfo`: 16 u64 params, 8 u64 fees, the state data, then the pubkeys.
+const AMM_INFO_LEN: usize = 752;
+const AMM_SWAP_FEE_NUMERATOR: usize = 176;
+const AMM_SWAP_FEE_DENOMINATOR: usize = 184;
+const AMM_COIN_VAULT: usize = 336;
+const AMM_PC_VAULT: usize = 368;
+const AMM_COIN_MINT: usize = 400;
+const AMM_PC_MINT: usize = 432;
+const AMM_OPEN_ORDERS: usize = 496;
+const AMM_MARKET: usize = 528;
+const AMM_MARKET_PROGRAM: usize = 560;
+
+/// OpenBook (Serum v3) `MarketState`, behind its 5 byte "serum" head.
+const MARKET_LEN: usize = 388;
+const MARKET_VAULT_SIGNER_NONCE: usize = 45;
+const MARKET_COIN_VAULT: usize = 117;
+const MARKET_PC_VAULT: usize = 165;
+const MARKET_EVENT_QUEUE: usize = 253;
+const MARKET_BIDS: usize = 285;
+const MARKET_ASKS: usize = 317;
+
+/// Every account of an AMM v4 swap except the user's, in `swap_base_in` order.
+#[derive(Debug, Clone)]
+pub struct RaydiumSwapAccounts {
+    pub amm_program: Pubkey,
+    pub amm_pool: Pubkey,
+    pub amm_authority: Pubkey,
+    pub amm_open_orders: Pubkey,
+    pub amm_coin_vault: Pubkey,
+    pub amm_pc_vault: Pubkey,
+    pub market_program: Pubkey,
+    pub market: Pubkey,
+    pub market_bids: Pubkey,
+    pub market_asks: Pubkey,
+    pub market_event_queue: Pubkey,
+    pub market_coin_vault: Pubkey,
+    pub market_pc_vault: Pubkey,
+    pub market_vault_signer: Pubkey,
+    pub coin_mint: Pubkey,
+    pub pc_mint: Pubkey,
+    pub fees: RaydiumFees,
+}
+
+impl RaydiumSwapAccounts {
+    /// The 17 accounts of a swap (no target orders), completed with the user's token
+    /// accounts and the signing owner.
+    pub fn swap_accounts(&self, user_source: &Pubkey, user_destination: &Pubkey, owner: &Pubkey) -> Vec<AccountMeta> {
+        vec![
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+            AccountMeta::new(self.amm_pool, false),
+            AccountMeta::new_readonly(self.amm_authority, false),
+            AccountMeta::new(self.amm_open_orders, false),
+            AccountMeta::new(self.amm_coin_vault, false),
+            AccountMeta::new(self.amm_pc_vault, false),
+            AccountMeta::new_readonly(self.market_program, false),
+            AccountMeta::new(self.market, false),
+            AccountMeta::new(self.market_bids, false),
+            AccountMeta::new(self.market_asks, false),
+            AccountMeta::new(self.market_event_queue, false),
+            AccountMeta::new(self.market_coin_vault, false),
+            AccountMeta::new(self.market_pc_vault, false),
+            AccountMeta::new_readonly(self.market_vault_signer, false),
+            AccountMeta::new(*user_source, false),
+            AccountMeta::new(*user_destination, false),
+            AccountMeta::new_readonly(*owner, true),
+        ]
+    }
+}
+
+/// Resolves the swap accounts of any AMM v4 pool from its pool state and OpenBook
+/// market over RPC, for copies and exits that have no target account list to
+/// borrow them from. Pools never change markets, so results are cached for good.
+pub struct PoolResolver {
+    rpc: Arc<dyn ChainRpc>,
+    amm_program: Pubkey,
+    cache: Mutex<HashMap<Pubkey, Arc<RaydiumSwapAccounts>>>,
+}
+
+impl PoolResolver {
+    pub fn new(config: &Config) -> Result<Self> {
+        Ok(Self::with_rpc(Arc::new(RpcClient::new(&config.rpc.endpoint)), Pubkey::from_str(&config.dex.raydium.program_id)?))
+    }
+
+    pub fn with_rpc(rpc: Arc<dyn ChainRpc>, amm_program: Pubkey) -> Self {
+        Self { rpc, amm_program, cache: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn cached(&self, pool: &Pubkey) -> Option<Arc<RaydiumSwapAccounts>> {
+        match self.cache.lock() {
+            Ok(cache) => cache.get(pool).cloned(),
+            Err(poisoned) => poisoned.into_inner().get(pool).cloned(),
+        }
+    }
+
+    pub async fn resolve(&self, pool: &Pubkey) -> Result<Arc<RaydiumSwapAccounts>> {
+        if let Some(accounts) = self.cached(pool) {
+            return Ok(accounts);
+        }
+
+        let amm = self.rpc.get_account(pool).await?
+            .ok_or_else(|| anyhow!("Pool {} not found", pool))?;
+        if amm.owner != self.amm_program || amm.data.len() < AMM_INFO_LEN {
+            return Err(anyhow!("{} is not an AMM v4 pool", pool));
+        }
+        let market = pubkey_at(&amm.data, AMM_MARKET)?;
+        let market_program = pubkey_at(&amm.data, AMM_MARKET_PROGRAM)?;
+
+        let market_account = self.rpc.get_account(&market).await?
+            .ok_or_else(|| anyhow!("Market {} of pool {} not found", market, pool))?;
+        if market_account.owner != market_program || market_account.data.len() < MARKET_LEN {
+            return Err(anyhow!("{} is not a market of {}", market, market_program));
+        }
+        let nonce = u64_at(&market_account.data, MARKET_VAULT_SIGNER_NONCE)?;
+        let market_vault_signer = Pubkey::create_program_address(&[market.as_ref(), &nonce.to_le_bytes()], &market_program)
+            .map_err(|e| anyhow!("Invalid vault signer nonce for market {}: {}", market, e))?;
+
+        let accounts = Arc::new(RaydiumSwapAccounts {
+            amm_program: self.amm_program,
+            amm_pool: *pool,
+            amm_authority: RAYDIUM_AMM_AUTHORITY,
+            amm_open_orders: pubkey_at(&amm.data, AMM_OPEN_ORDERS)?,
+            amm_coin_vault: pubkey_at(&amm.data, AMM_COIN_VAULT)?,
+            amm_pc_vault: pubkey_at(&amm.data, AMM_PC_VAULT)?,
+            market_program,
+            market,
+            market_bids: pubkey_at(&market_account.data, MARKET_BIDS)?,
+            market_asks: pubkey_at(&market_account.data, MARKET_ASKS)?,
+            market_event_queue: pubkey_at(&market_account.data, MARKET_EVENT_QUEUE)?,
+            market_coin_vault: pubkey_at(&market_account.data, MARKET_COIN_VAULT)?,
+            market_pc_vault: pubkey_at(&market_account.data, MARKET_PC_VAULT)?,
+            market_vault_signer,
+            coin_mint: pubkey_at(&amm.data, AMM_COIN_MINT)?,
+            pc_mint: pubkey_at(&amm.data, AMM_PC_MINT)?,
+            fees: RaydiumFees {
+                numerator: u64_at(&amm.data, AMM_SWAP_FEE_NUMERATOR)?,
+                denominator: u64_at(&amm.data, AMM_SWAP_FEE_DENOMINATOR)?,
+            },
+        });
+        info!("Resolved Raydium pool {} on market {}", pool, market);
+
+        match self.cache.lock() {
+            Ok(mut cache) => cache.insert(*pool, accounts.clone()),
+            Err(poisoned) => poisoned.into_inner().insert(*pool, accounts.clone()),
+        };
+        Ok(accounts)
+    }
+}
+
+fn pubkey_at(data: &[u8], offset: usize) -> Result<Pubkey> {
+    let bytes: [u8; 32] = data.get(offset..offset + 32)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow!("Account data too short for a pubkey at {}", offset))?;
+    Ok(Pubkey::new_from_array(bytes))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Result<u64> {
+    let bytes: [u8; 8] = data.get(offset..offset + 8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow!("Account data too short for a u64 at {}", offset))?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{AccountData, MockRpc};
+
+    const AMM_PROGRAM: Pubkey = pubkey!("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8");
+    const OPENBOOK_PROGRAM: Pubkey = pubkey!("srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX");
+
+    fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    #[tokio::test]
+    async fn resolves_a_pool_from_its_amm_and_market_accounts() {
+        let pool = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let [open_orders, coin_vault, pc_vault, coin_mint, pc_mint] = [(); 5].map(|_| Pubkey::new_unique());
+        let [bids, asks, event_queue, market_coin_vault, market_pc_vault] = [(); 5].map(|_| Pubkey::new_unique());
+        // OpenBook stores the first nonce that derives a valid vault signer
+        let (nonce, vault_signer) = (0u64..)
+            .find_map(|nonce| Pubkey::create_program_address(&[market.as_ref(), &nonce.to_le_bytes()], &OPENBOOK_PROGRAM)
+                .ok()
+                .map(|signer| (nonce, signer)))
+            .unwrap();
+
+        let mut amm = vec![0u8; AMM_INFO_LEN];
+        put(&mut amm, AMM_SWAP_FEE_NUMERATOR, &25u64.to_le_bytes());
+        put(&mut amm, AMM_SWAP_FEE_DENOMINATOR, &10_000u64.to_le_bytes());
+        put(&mut amm, AMM_COIN_VAULT, coin_vault.as_ref());
+        put(&mut amm, AMM_PC_VAULT, pc_vault.as_ref());
+        put(&mut amm, AMM_COIN_MINT, coin_mint.as_ref());
+        put(&mut amm, AMM_PC_MINT, pc_mint.as_ref());
+        put(&mut amm, AMM_OPEN_ORDERS, open_orders.as_ref());
+        put(&mut amm, AMM_MARKET, market.as_ref());
+        put(&mut amm, AMM_MARKET_PROGRAM, OPENBOOK_PROGRAM.as_ref());
+
+        let mut market_data = vec![0u8; MARKET_LEN];
+        put(&mut market_data, 0, b"serum");
+        put(&mut market_data, MARKET_VAULT_SIGNER_NONCE, &nonce.to_le_bytes());
+        put(&mut market_data, MARKET_COIN_VAULT, market_coin_vault.as_ref());
+        put(&mut market_data, MARKET_PC_VAULT, market_pc_vault.as_ref());
+        put(&mut market_data, MARKET_EVENT_QUEUE, event_queue.as_ref());
+        put(&mut market_data, MARKET_BIDS, bids.as_ref());
+        put(&mut market_data, MARKET_ASKS, asks.as_ref());
+
+        let mut rpc = MockRpc::default();
+        rpc.accounts.insert(pool, AccountData { owner: AMM_PROGRAM, data: amm });
+        rpc.accounts.insert(market, AccountData { owner: OPENBOOK_PROGRAM, data: market_data });
+        let resolver = PoolResolver::with_rpc(Arc::new(rpc), AMM_PROGRAM);
+
+        let accounts = resolver.resolve(&pool).await.unwrap();
+        assert_eq!(accounts.amm_program, AMM_PROGRAM);
+        assert_eq!(accounts.amm_pool, pool);
+        assert_eq!(accounts.amm_authority, RAYDIUM_AMM_AUTHORITY);
+        assert_eq!(accounts.amm_open_orders, open_orders);
+        assert_eq!(accounts.amm_coin_vault, coin_vault);
+        assert_eq!(accounts.amm_pc_vault, pc_vault);
+        assert_eq!(accounts.market_program, OPENBOOK_PROGRAM);
+        assert_eq!(accounts.market, market);
+        assert_eq!(accounts.market_bids, bids);
+        assert_eq!(accounts.market_asks, asks);
+        assert_eq!(accounts.market_event_queue, event_queue);
+        assert_eq!(accounts.market_coin_vault, market_coin_vault);
+        assert_eq!(accounts.market_pc_vault, market_pc_vault);
+        assert_eq!(accounts.market_vault_signer, vault_signer);
+        assert_eq!(accounts.coin_mint, coin_mint);
+        assert_eq!(accounts.pc_mint, pc_mint);
+        assert_eq!(accounts.fees, RaydiumFees { numerator: 25, denominator: 10_000 });
+        assert!(resolver.cached(&pool).is_some());
+    }
+
+    #[tokio::test]
+    async fn rejects_accounts_of_another_program() {
+        let pool = Pubkey::new_unique();
+        let mut rpc = MockRpc::default();
+        rpc.accounts.insert(pool, AccountData { owner: Pubkey::new_unique(), data: vec![0u8; AMM_INFO_LEN] });
+        let resolver = PoolResolver::with_rpc(Arc::new(rpc), AMM_PROGRAM);
+
+        assert!(resolver.resolve(&pool).await.is_err());
+        assert!(resolver.resolve(&Pubkey::new_unique()).await.is_err());
+        assert!(resolver.cached(&pool).is_none());
+    }
+}
@@ -0,0 +1,159 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::time::{interval, Duration};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What is persisted: the last processed slot and the signatures already handled in
+/// it, so a replay starting at that slot can skip them.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedCheckpoint {
+    slot: u64,
+    signatures: Vec<String>,
+}
+
+/// Last processed slot of a monitor, shared between the processor that advances it
+/// and the feeds that resubscribe from it with `from_slot` after a disconnect.
+pub struct SlotCheckpoint {
+    path: PathBuf,
+    slot: AtomicU64,
+    saved: Mutex<SavedCheckpoint>,
+    /// Whether `saved` moved since it was last written
+    dirty: AtomicBool,
+}
+
+impl SlotCheckpoint {
+    /// Loads the checkpoint from `path`, starting empty if it doesn't exist yet.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let saved = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => SavedCheckpoint::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            slot: AtomicU64::new(saved.slot),
+            saved: Mutex::new(saved),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    /// Slot to resubscribe from, if anything has been processed yet.
+    pub fn resume_slot(&self) -> Option<u64> {
+        match self.slot.load(Ordering::Relaxed) {
+            0 => None,
+            slot => Some(slot),
+        }
+    }
+
+    /// Whether `signature` was handled in the checkpointed slot, before a restart or a
+    /// disconnect, and is delivered again by a resubscription from that slot. Kept
+    /// until the checkpoint moves past the slot, however long the outage was.
+    pub fn handled(&self, slot: u64, signature: &str) -> bool {
+        let saved = match self.saved.lock() {
+            Ok(saved) => saved,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        slot == saved.slot && saved.signatures.iter().any(|handled| handled == signature)
+    }
+
+    pub fn record_transaction(&self, slot: u64, signature: &str) {
+        self.update(slot, Some(signature));
+    }
+
+    pub fn record_slot(&self, slot: u64) {
+        self.update(slot, None);
+    }
+
+    fn update(&self, slot: u64, signature: Option<&str>) {
+        let mut saved = match self.saved.lock() {
+            Ok(saved) => saved,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if slot < saved.slot {
+            return;
+        }
+        if slot > saved.slot {
+            saved.slot = slot;
+            saved.signatures.clear();
+            self.slot.store(slot, Ordering::Relaxed);
+        }
+        if let Some(signature) = signature {
+            saved.signatures.push(signature.to_owned());
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Spawns the task that writes the checkpoint to disk every `FLUSH_INTERVAL` if it
+    /// moved, off the async workers. A crash loses at most that much progress, whose
+    /// transactions are then handled again after the restart.
+    pub fn start(self: &Arc<Self>) {
+        let checkpoint = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = interval(FLUSH_INTERVAL);
+            loop {
+                ticker.tick().await;
+                if !checkpoint.dirty.swap(false, Ordering::Relaxed) {
+                    continue;
+                }
+                let writer = checkpoint.clone();
+                if let Err(e) = tokio::task::spawn_blocking(move || writer.write()).await {
+                    warn!("Slot checkpoint writer failed: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Writes the checkpoint to disk now, on shutdown.
+    pub fn flush(&self) {
+        self.dirty.store(false, Ordering::Relaxed);
+        self.write();
+    }
+
+    fn write(&self) {
+        let content = {
+            let saved = match self.saved.lock() {
+                Ok(saved) => saved,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            serde_json::to_string(&*saved)
+        };
+        let result = content.map_err(anyhow::Error::from).and_then(|content| {
+            let tmp_path = self.path.with_extension("tmp");
+            std::fs::write(&tmp_path, content)?;
+            std::fs::rename(&tmp_path, &self.path)?;
+            Ok(())
+        });
+        if let Err(e) = result {
+            warn!("Failed to persist slot checkpoint {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_are_handled_until_the_slot_is_passed() {
+        let path = std::env::temp_dir().join(format!("checkpoint-test-{}.json", rand::random::<u64>()));
+        let checkpoint = SlotCheckpoint::load(path.clone()).unwrap();
+        checkpoint.record_transaction(10, "first");
+        assert!(checkpoint.handled(10, "first"));
+        assert!(!checkpoint.handled(10, "second"));
+
+        // A restart keeps them
+        checkpoint.flush();
+        let restored = SlotCheckpoint::load(path.clone()).unwrap();
+        assert_eq!(restored.resume_slot(), Some(10));
+        assert!(restored.handled(10, "first"));
+
+        restored.record_slot(11);
+        assert!(!restored.handled(10, "first"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
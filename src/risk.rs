@@ -0,0 +1,341 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::time::{interval, Duration};
+use crate::config::{Config, RiskConfig};
+
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+const SECS_PER_DAY: u64 = 86_400;
+const BUY_RATE_WINDOW: Duration = Duration::from_secs(60);
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What survives a restart: the exposure, the recent buys, the day's realized PnL,
+/// and the kill switch.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RiskState {
+    /// Days since the unix epoch, UTC
+    day: u64,
+    realized_pnl: i64,
+    halted: bool,
+    /// Lamports deployed per mint, across targets
+    #[serde(default)]
+    exposure: HashMap<String, u64>,
+    /// Unix milliseconds of the buys in the rate window
+    #[serde(default)]
+    buys: VecDeque<u64>,
+}
+
+/// Global limits consulted before every copied buy. Sells are never blocked.
+/// Clearing the daily loss halt takes a restart with `reset_halt` set.
+pub struct RiskManager {
+    config: RiskConfig,
+    path: PathBuf,
+    state: Mutex<RiskState>,
+    /// Whether `state` moved since it was last written
+    dirty: AtomicBool,
+}
+
+impl RiskManager {
+    /// Loads the risk state from `risk.state_dir`. The kill switch is cleared if
+    /// `reset_halt` is set.
+    pub fn load(config: &Config) -> Result<Self> {
+        let path = Path::new(&config.risk.state_dir).join("risk.json");
+        let mut state: RiskState = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RiskState::default(),
+            Err(e) => return Err(e.into()),
+        };
+        if state.halted && config.risk.reset_halt {
+            info!("Risk kill switch reset from config");
+            state.halted = false;
+        } else if state.halted {
+            error!("ALERT buying is halted by the daily loss limit, restart with risk.reset_halt set to resume");
+        }
+        if !state.exposure.is_empty() {
+            info!(
+                "Risk exposure of {:.3} SOL in {} mints carried over",
+                state.exposure.values().sum::<u64>() as f64 / LAMPORTS_PER_SOL, state.exposure.len()
+            );
+        }
+        let manager = Self {
+            config: config.risk.clone(),
+            path,
+            state: Mutex::new(state),
+            dirty: AtomicBool::new(false),
+        };
+        manager.flush();
+        Ok(manager)
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, RiskState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Spawns the task that writes the risk state to disk every `FLUSH_INTERVAL` if it
+    /// moved, off the async workers.
+    pub fn start(self: &Arc<Self>) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = interval(FLUSH_INTERVAL);
+            loop {
+                ticker.tick().await;
+                if !manager.dirty.swap(false, Ordering::Relaxed) {
+                    continue;
+                }
+                let writer = manager.clone();
+                if let Err(e) = tokio::task::spawn_blocking(move || writer.write()).await {
+                    warn!("Risk state writer failed: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Writes the risk state to disk now, on startup and shutdown.
+    pub fn flush(&self) {
+        self.dirty.store(false, Ordering::Relaxed);
+        self.write();
+    }
+
+    /// Admits a buy of `mint` trimmed to the room left under the exposure limits, and
+    /// reserves it right away, so concurrent buys cannot all pass the same check.
+    /// Returns the reason if the limits refuse it.
+    pub fn admit_buy(self: &Arc<Self>, mint: &str, lamports: u64) -> Result<BuyReservation, String> {
+        let mut state = self.state();
+        if state.halted {
+            return Err("buying is halted by the daily loss limit".to_owned());
+        }
+        let now = unix_millis();
+        let window_start = now.saturating_sub(BUY_RATE_WINDOW.as_millis() as u64);
+        while state.buys.front().is_some_and(|&at| at < window_start) {
+            state.buys.pop_front();
+        }
+        if state.buys.len() >= self.config.max_buys_per_minute {
+            return Err(format!("{} buys in the last minute", state.buys.len()));
+        }
+        let in_mint = state.exposure.get(mint).copied();
+        if in_mint.is_none() && state.exposure.len() >= self.config.max_positions {
+            return Err(format!("{} positions already open", state.exposure.len()));
+        }
+        let total: u64 = state.exposure.values().sum();
+        let room = lamports_of(self.config.max_exposure).saturating_sub(total)
+            .min(lamports_of(self.config.max_per_mint).saturating_sub(in_mint.unwrap_or(0)));
+        if room == 0 {
+            return Err(format!("exposure limit reached ({:.3} SOL deployed)", total as f64 / LAMPORTS_PER_SOL));
+        }
+        let lamports = lamports.min(room);
+        state.buys.push_back(now);
+        *state.exposure.entry(mint.to_owned()).or_default() += lamports;
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(BuyReservation { risk: self.clone(), mint: mint.to_owned(), lamports, admitted_at: now, settled: false })
+    }
+
+    /// Replaces the reservation of a buy with the lamports it actually spent. A buy
+    /// that was never sent also gives back its place in the rate window.
+    fn settle_buy(&self, reservation: &BuyReservation, spent: Option<u64>) {
+        let mut state = self.state();
+        let exposure = state.exposure.entry(reservation.mint.clone()).or_default();
+        *exposure = exposure.saturating_sub(reservation.lamports) + spent.unwrap_or(0);
+        if *exposure == 0 {
+            state.exposure.remove(&reservation.mint);
+        }
+        if spent.is_none() {
+            if let Some(index) = state.buys.iter().position(|&at| at == reservation.admitted_at) {
+                state.buys.remove(index);
+            }
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Releases the cost basis of the tokens sold and books the realized PnL, tripping
+    /// the kill switch once the day's loss reaches the limit.
+    pub fn record_sell(&self, mint: &str, cost: u64, pnl: i64) {
+        let mut state = self.state();
+        if let Some(exposure) = state.exposure.get_mut(mint) {
+            *exposure = exposure.saturating_sub(cost);
+            if *exposure == 0 {
+                state.exposure.remove(mint);
+            }
+        }
+        let today = today();
+        if state.day != today {
+            state.day = today;
+            state.realized_pnl = 0;
+        }
+        state.realized_pnl += pnl;
+        if !state.halted && state.realized_pnl <= -(lamports_of(self.config.daily_loss_limit) as i64) {
+            state.halted = true;
+            error!(
+                "ALERT daily realized loss of {:.3} SOL hit the limit, buying halted",
+                -state.realized_pnl as f64 / LAMPORTS_PER_SOL
+            );
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    fn write(&self) {
+        let content = serde_json::to_string(&*self.state());
+        let result = content.map_err(anyhow::Error::from).and_then(|content| {
+            if let Some(dir) = self.path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let tmp_path = self.path.with_extension("tmp");
+            std::fs::write(&tmp_path, content)?;
+            std::fs::rename(&tmp_path, &self.path)?;
+            Ok(())
+        });
+        if let Err(e) = result {
+            warn!("Failed to persist risk state {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Lamports of an admitted buy, held against the limits until the buy is settled.
+/// Dropping it unsettled, when the order is given up before it is sent, releases them.
+pub struct BuyReservation {
+    risk: Arc<RiskManager>,
+    mint: String,
+    lamports: u64,
+    /// Unix milliseconds, its entry in the rate window
+    admitted_at: u64,
+    settled: bool,
+}
+
+impl BuyReservation {
+    pub fn lamports(&self) -> u64 {
+        self.lamports
+    }
+
+    /// Settles a sent buy at the lamports it spent, zero if it did not land.
+    pub fn settle(mut self, spent: u64) {
+        self.risk.settle_buy(&self, Some(spent));
+        self.settled = true;
+    }
+}
+
+impl Drop for BuyReservation {
+    fn drop(&mut self) {
+        if !self.settled {
+            self.risk.settle_buy(self, None);
+        }
+    }
+}
+
+fn lamports_of(sol: f64) -> u64 {
+    (sol * LAMPORTS_PER_SOL) as u64
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_millis() as u64).unwrap_or_default()
+}
+
+fn today() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs() / SECS_PER_DAY).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOL: u64 = 1_000_000_000;
+
+    fn manager(max_positions: usize, max_buys_per_minute: usize) -> Arc<RiskManager> {
+        let config = RiskConfig {
+            state_dir: String::new(),
+            max_exposure: 1.0,
+            max_positions,
+            max_per_mint: 0.6,
+            max_buys_per_minute,
+            daily_loss_limit: 0.5,
+            reset_halt: false,
+        };
+        let path = std::env::temp_dir().join(format!("risk-test-{}.json", rand::random::<u64>()));
+        Arc::new(RiskManager { config, path, state: Mutex::new(RiskState::default()), dirty: AtomicBool::new(false) })
+    }
+
+    fn exposure(risk: &RiskManager) -> u64 {
+        risk.state().exposure.values().sum()
+    }
+
+    #[test]
+    fn buys_are_trimmed_to_the_exposure_limits() {
+        let risk = manager(10, 10);
+        let first = risk.admit_buy("a", SOL / 2).unwrap();
+        assert_eq!(first.lamports(), SOL / 2);
+        // 0.6 SOL per mint
+        let second = risk.admit_buy("a", SOL / 2).unwrap();
+        assert_eq!(second.lamports(), SOL / 10);
+        assert!(risk.admit_buy("a", SOL / 2).is_err());
+        // 1 SOL in total
+        let other = risk.admit_buy("b", SOL).unwrap();
+        assert_eq!(other.lamports(), 4 * SOL / 10);
+        assert!(risk.admit_buy("c", SOL).is_err());
+    }
+
+    #[test]
+    fn positions_and_buy_rate_are_capped() {
+        let risk = manager(2, 3);
+        let _a = risk.admit_buy("a", SOL / 10).unwrap();
+        let _b = risk.admit_buy("b", SOL / 10).unwrap();
+        assert!(risk.admit_buy("c", SOL / 10).is_err());
+        let _more_a = risk.admit_buy("a", SOL / 10).unwrap();
+        assert!(risk.admit_buy("a", SOL / 10).is_err());
+    }
+
+    #[test]
+    fn unsent_buys_release_their_reservation() {
+        let risk = manager(10, 1);
+        let reservation = risk.admit_buy("a", SOL / 2).unwrap();
+        assert_eq!(exposure(&risk), SOL / 2);
+        assert!(risk.admit_buy("b", SOL / 10).is_err());
+        drop(reservation);
+        assert_eq!(exposure(&risk), 0);
+        assert!(risk.admit_buy("b", SOL / 10).is_ok());
+    }
+
+    #[test]
+    fn settled_buys_hold_what_they_spent() {
+        let risk = manager(10, 10);
+        risk.admit_buy("a", SOL / 2).unwrap().settle(SOL / 4);
+        assert_eq!(exposure(&risk), SOL / 4);
+        risk.admit_buy("b", SOL / 2).unwrap().settle(0);
+        assert!(!risk.state().exposure.contains_key("b"));
+        // A sent buy keeps its place in the rate window
+        assert_eq!(risk.state().buys.len(), 2);
+    }
+
+    #[test]
+    fn daily_loss_halts_buying() {
+        let risk = manager(10, 10);
+        risk.admit_buy("a", SOL / 2).unwrap().settle(SOL / 2);
+        risk.record_sell("a", SOL / 2, -(SOL as i64) / 2);
+        assert_eq!(exposure(&risk), 0);
+        assert!(risk.admit_buy("b", SOL / 10).is_err());
+    }
+
+    #[test]
+    fn concurrent_buys_cannot_overshoot_the_limits() {
+        let risk = manager(3, 100);
+        let admitted: Vec<u64> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..16)
+                .map(|i| {
+                    let risk = risk.clone();
+                    // Held until every thread is done, like buys in flight
+                    scope.spawn(move || risk.admit_buy(&format!("mint-{}", i), 3 * SOL / 10).ok())
+                })
+                .collect();
+            let reservations: Vec<BuyReservation> = handles.into_iter().filter_map(|handle| handle.join().unwrap()).collect();
+            reservations.iter().map(BuyReservation::lamports).collect()
+        });
+        assert_eq!(admitted.len(), 3);
+        assert!(admitted.iter().sum::<u64>() <= SOL);
+        assert_eq!(risk.state().buys.len(), 0);
+    }
+}
@@ -0,0 +1,376 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use solana_sdk::{
+    hash::Hash, message::v0::LoadedAddresses, instruction::CompiledInstruction, pubkey::Pubkey, signature::Signature,
+    transaction::Transaction,
+};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, InnerInstruction, InnerInstructions, TransactionStatusMeta,
+    TransactionTokenBalance, TransactionWithStatusMeta, UiInstruction, UiLoadedAddresses, UiTransactionStatusMeta,
+    UiTransactionTokenBalance,
+    VersionedTransactionWithStatusMeta,
+};
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::Mutex;
+use base64::prelude::{Engine, BASE64_STANDARD};
+
+/// The chain reads of the execution pipeline: the executor, the pool and mint
+/// resolvers, the chain cache and the confirmation tracker. Implemented by
+/// `RpcClient`, and by `MockRpc` to run the pipeline offline.
+#[async_trait]
+pub trait ChainRpc: Send + Sync {
+    async fn get_account(&self, address: &Pubkey) -> Result<Option<AccountData>>;
+    async fn get_latest_blockhash(&self) -> Result<(Hash, u64)>;
+    async fn get_recent_prioritization_fees(&self, writable: &[Pubkey]) -> Result<Vec<u64>>;
+    async fn get_block_height(&self) -> Result<u64>;
+    async fn get_signature_statuses(&self, signatures: &[Signature]) -> Result<Vec<Option<SignatureStatus>>>;
+    async fn get_transaction(&self, signature: &str) -> Result<Option<(u64, TransactionWithStatusMeta)>>;
+}
+
+/// Minimal JSON-RPC client for the few Solana RPC methods the bot needs.
+#[derive(Clone)]
+pub struct RpcClient {
+    http: reqwest::Client,
+    endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcContext<T> {
+    value: T,
+}
+
+#[derive(Debug, Deserialize)]
+struct UiAccount {
+    /// `[data, encoding]`
+    data: (String, String),
+    owner: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenAccountBalance {
+    address: String,
+    amount: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockhashInfo {
+    blockhash: String,
+    last_valid_block_height: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PrioritizationFee {
+    prioritization_fee: u64,
+}
+
+/// Status of a signature as returned by `getSignatureStatuses`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureStatus {
+    pub slot: u64,
+    pub err: Option<Value>,
+    /// `processed`, `confirmed` or `finalized`
+    pub confirmation_status: Option<String>,
+}
+
+/// Raw account as returned by `getAccountInfo`.
+#[derive(Debug, Clone)]
+pub struct AccountData {
+    pub owner: Pubkey,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignatureInfo {
+    pub signature: String,
+    pub err: Option<Value>,
+    #[serde(rename = "blockTime")]
+    pub block_time: Option<i64>,
+}
+
+impl RpcClient {
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            endpoint: endpoint.to_owned(),
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<Option<T>> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+        let response: RpcResponse<T> = self.http.post(&self.endpoint)
+            .json(&request)
+            .send()
+            .await
+            .with_context(|| format!("{} request failed", method))?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("Invalid {} response", method))?;
+        if let Some(error) = response.error {
+            return Err(anyhow!("{} failed: {}", method, error));
+        }
+        Ok(response.result)
+    }
+
+    /// Fetches an account's owner and data, or `None` if it doesn't exist.
+    pub async fn get_account(&self, address: &Pubkey) -> Result<Option<AccountData>> {
+        let config = json!({ "encoding": "base64", "commitment": "confirmed" });
+        let response: Option<RpcContext<Option<UiAccount>>> =
+            self.call("getAccountInfo", json!([address.to_string(), config])).await?;
+        let Some(account) = response.and_then(|response| response.value) else {
+            return Ok(None);
+        };
+        Ok(Some(AccountData {
+            owner: Pubkey::from_str(&account.owner)?,
+            data: BASE64_STANDARD.decode(&account.data.0).context("Invalid account data")?,
+        }))
+    }
+
+    /// The largest token accounts of `mint` (up to 20) and their raw amounts.
+    pub async fn get_token_largest_accounts(&self, mint: &Pubkey) -> Result<Vec<(Pubkey, u64)>> {
+        let config = json!({ "commitment": "confirmed" });
+        let response: Option<RpcContext<Vec<TokenAccountBalance>>> =
+            self.call("getTokenLargestAccounts", json!([mint.to_string(), config])).await?;
+        response.map(|response| response.value).unwrap_or_default().into_iter()
+            .map(|balance| Ok((Pubkey::from_str(&balance.address)?, balance.amount.parse()?)))
+            .collect()
+    }
+
+    /// Signatures of transactions involving `address`, newest first.
+    pub async fn get_signatures_for_address(&self, address: &str, before: Option<&str>, limit: usize) -> Result<Vec<SignatureInfo>> {
+        let mut config = json!({ "limit": limit, "commitment": "confirmed" });
+        if let Some(before) = before {
+            config["before"] = json!(before);
+        }
+        Ok(self.call("getSignaturesForAddress", json!([address, config])).await?.unwrap_or_default())
+    }
+
+    /// Latest confirmed blockhash and the last block height at which transactions
+    /// using it can land.
+    pub async fn get_latest_blockhash(&self) -> Result<(Hash, u64)> {
+        let config = json!({ "commitment": "confirmed" });
+        let response: Option<RpcContext<BlockhashInfo>> = self.call("getLatestBlockhash", json!([config])).await?;
+        let info = response.ok_or_else(|| anyhow!("Empty getLatestBlockhash response"))?.value;
+        Ok((Hash::from_str(&info.blockhash)?, info.last_valid_block_height))
+    }
+
+    /// Lowest compute unit price, in micro-lamports, that landed a transaction writing
+    /// all of `writable` (any transaction if it is empty) in each of the recent slots
+    /// the node keeps (up to 150).
+    pub async fn get_recent_prioritization_fees(&self, writable: &[Pubkey]) -> Result<Vec<u64>> {
+        let writable: Vec<String> = writable.iter().map(|key| key.to_string()).collect();
+        let fees: Vec<PrioritizationFee> = self.call("getRecentPrioritizationFees", json!([writable])).await?.unwrap_or_default();
+        Ok(fees.into_iter().map(|fee| fee.prioritization_fee).collect())
+    }
+
+    pub async fn get_block_height(&self) -> Result<u64> {
+        let config = json!({ "commitment": "confirmed" });
+        self.call("getBlockHeight", json!([config])).await?
+            .ok_or_else(|| anyhow!("Empty getBlockHeight response"))
+    }
+
+    /// Sends a signed transaction. The node does not retry it: resending is up to the
+    /// caller.
+    pub async fn send_transaction(&self, transaction: &Transaction, skip_preflight: bool) -> Result<Signature> {
+        let encoded = BASE64_STANDARD.encode(bincode::serialize(transaction)?);
+        let config = json!({ "encoding": "base64", "skipPreflight": skip_preflight, "maxRetries": 0 });
+        let signature: String = self.call("sendTransaction", json!([encoded, config])).await?
+            .ok_or_else(|| anyhow!("Empty sendTransaction response"))?;
+        Ok(Signature::from_str(&signature)?)
+    }
+
+    /// Statuses of `signatures`, `None` for the ones the node has not seen.
+    pub async fn get_signature_statuses(&self, signatures: &[Signature]) -> Result<Vec<Option<SignatureStatus>>> {
+        let signatures: Vec<String> = signatures.iter().map(|signature| signature.to_string()).collect();
+        let response: Option<RpcContext<Vec<Option<SignatureStatus>>>> =
+            self.call("getSignatureStatuses", json!([signatures])).await?;
+        Ok(response.map(|response| response.value).unwrap_or_default())
+    }
+
+    /// Fetches a confirmed transaction in the same shape the gRPC feed delivers it.
+    pub async fn get_transaction(&self, signature: &str) -> Result<Option<(u64, TransactionWithStatusMeta)>> {
+        let config = json!({
+            "encoding": "base64",
+            "commitment": "confirmed",
+            "maxSupportedTransactionVersion": 0,
+        });
+        let encoded: Option<EncodedConfirmedTransactionWithStatusMeta> =
+            self.call("getTransaction", json!([signature, config])).await?;
+        match encoded {
+            Some(encoded) => Ok(Some((encoded.slot, decode_transaction(encoded)?))),
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl ChainRpc for RpcClient {
+    async fn get_account(&self, address: &Pubkey) -> Result<Option<AccountData>> {
+        RpcClient::get_account(self, address).await
+    }
+
+    async fn get_latest_blockhash(&self) -> Result<(Hash, u64)> {
+        RpcClient::get_latest_blockhash(self).await
+    }
+
+    async fn get_recent_prioritization_fees(&self, writable: &[Pubkey]) -> Result<Vec<u64>> {
+        RpcClient::get_recent_prioritization_fees(self, writable).await
+    }
+
+    async fn get_block_height(&self) -> Result<u64> {
+        RpcClient::get_block_height(self).await
+    }
+
+    async fn get_signature_statuses(&self, signatures: &[Signature]) -> Result<Vec<Option<SignatureStatus>>> {
+        RpcClient::get_signature_statuses(self, signatures).await
+    }
+
+    async fn get_transaction(&self, signature: &str) -> Result<Option<(u64, TransactionWithStatusMeta)>> {
+        RpcClient::get_transaction(self, signature).await
+    }
+}
+
+fn decode_transaction(encoded: EncodedConfirmedTransactionWithStatusMeta) -> Result<TransactionWithStatusMeta> {
+    let transaction = encoded.transaction.transaction.decode()
+        .ok_or_else(|| anyhow!("Failed to decode transaction"))?;
+    let meta = encoded.transaction.meta
+        .ok_or_else(|| anyhow!("MissingMetadata"))?;
+    Ok(TransactionWithStatusMeta::Complete(VersionedTransactionWithStatusMeta {
+        transaction,
+        meta: decode_meta(meta)?,
+    }))
+}
+
+fn decode_meta(meta: UiTransactionStatusMeta) -> Result<TransactionStatusMeta> {
+    let inner_instructions: Option<Vec<_>> = meta.inner_instructions.into();
+    let inner_instructions = inner_instructions.map(|inner| {
+        inner.into_iter().map(|cii| {
+            let instructions = cii.instructions.into_iter().map(|ix| match ix {
+                UiInstruction::Compiled(ix) => Ok(InnerInstruction {
+                    instruction: CompiledInstruction {
+                        program_id_index: ix.program_id_index,
+                        accounts: ix.accounts,
+                        data: bs58::decode(&ix.data).into_vec()?,
+                    },
+                    stack_height: ix.stack_height,
+                }),
+                UiInstruction::Parsed(_) => Err(anyhow!("Unexpected parsed inner instruction")),
+            }).collect::<Result<Vec<_>>>()?;
+            Ok(InnerInstructions { index: cii.index, instructions })
+        }).collect::<Result<Vec<_>>>()
+    }).transpose()?;
+
+    let token_balances = |balances: Option<Vec<UiTransactionTokenBalance>>| {
+        balances.map(|balances| balances.into_iter().map(|balance| TransactionTokenBalance {
+            account_index: balance.account_index,
+            mint: balance.mint,
+            ui_token_amount: balance.ui_token_amount,
+            owner: Option::from(balance.owner).unwrap_or_default(),
+            program_id: Option::from(balance.program_id).unwrap_or_default(),
+        }).collect())
+    };
+
+    let loaded_addresses: Option<UiLoadedAddresses> = meta.loaded_addresses.into();
+    let loaded_addresses = match loaded_addresses {
+        Some(loaded) => {
+            let parse = |keys: Vec<String>| keys.iter().map(|key| Pubkey::from_str(key)).collect::<Result<Vec<_>, _>>();
+            LoadedAddresses {
+                writable: parse(loaded.writable)?,
+                readonly: parse(loaded.readonly)?,
+            }
+        }
+        None => LoadedAddresses::default(),
+    };
+
+    Ok(TransactionStatusMeta {
+        status: meta.status,
+        fee: meta.fee,
+        pre_balances: meta.pre_balances,
+        post_balances: meta.post_balances,
+        inner_instructions,
+        log_messages: meta.log_messages.into(),
+        pre_token_balances: token_balances(meta.pre_token_balances.into()),
+        post_token_balances: token_balances(meta.post_token_balances.into()),
+        rewards: meta.rewards.into(),
+        loaded_addresses,
+        return_data: None,
+        compute_units_consumed: meta.compute_units_consumed.into(),
+    })
+}
+
+/// Scripted chain: fixed accounts, blockhash, block height and fees, and one
+/// signature status per poll, then `landed` for every signature. Transactions are
+/// never found, so fills fall back to their quote.
+#[derive(Default)]
+pub struct MockRpc {
+    pub accounts: HashMap<Pubkey, AccountData>,
+    pub blockhash: (Hash, u64),
+    pub block_height: u64,
+    /// Fees per `writable` list asked for, none for the others
+    pub fees: HashMap<Vec<Pubkey>, Vec<u64>>,
+    /// Returned one per `getSignatureStatuses` call
+    pub statuses: Mutex<VecDeque<Option<SignatureStatus>>>,
+    /// Returned once `statuses` runs out, unseen if `None`
+    pub landed: Option<SignatureStatus>,
+}
+
+impl MockRpc {
+    /// A chain where every transaction is confirmed as soon as it is sent.
+    pub fn landing() -> Self {
+        let landed = SignatureStatus { slot: 0, err: None, confirmation_status: Some("confirmed".to_owned()) };
+        Self { landed: Some(landed), ..Default::default() }
+    }
+}
+
+#[async_trait]
+impl ChainRpc for MockRpc {
+    async fn get_account(&self, address: &Pubkey) -> Result<Option<AccountData>> {
+        Ok(self.accounts.get(address).cloned())
+    }
+
+    async fn get_latest_blockhash(&self) -> Result<(Hash, u64)> {
+        Ok(self.blockhash)
+    }
+
+    async fn get_recent_prioritization_fees(&self, writable: &[Pubkey]) -> Result<Vec<u64>> {
+        Ok(self.fees.get(writable).cloned().unwrap_or_default())
+    }
+
+    async fn get_block_height(&self) -> Result<u64> {
+        Ok(self.block_height)
+    }
+
+    async fn get_signature_statuses(&self, signatures: &[Signature]) -> Result<Vec<Option<SignatureStatus>>> {
+        let scripted = match self.statuses.lock() {
+            Ok(mut statuses) => statuses.pop_front(),
+            Err(poisoned) => poisoned.into_inner().pop_front(),
+        };
+        let status = scripted.unwrap_or_else(|| self.landed.clone());
+        Ok(signatures.iter().map(|_| status.clone()).collect())
+    }
+
+    async fn get_transaction(&self, _signature: &str) -> Result<Option<(u64, TransactionWithStatusMeta)>> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use futures::stream::{self, StreamExt};
+use log::warn;
+use solana_sdk::pubkey::Pubkey;
+use crate::config::{Config, SafetyConfig};
+use crate::execution::associated_token_address;
+use crate::mints::MintInfo;
+use crate::parser::flatten_transaction_response;
+use crate::resolver::PoolResolver;
+use crate::rpc::RpcClient;
+use crate::trade::{Dex, TradeEvent};
+use crate::utils::pumpfun_parser::{PumpfunInstruction, PumpfunParser, PUMPFUN_PROGRAM_ID};
+
+/// AMM v4 `AmmInfo` LP mint, and LP supply as accounted by the pool. LP burned
+/// through the token program leaves the latter unchanged.
+const AMM_LP_MINT: usize = 464;
+const AMM_LP_AMOUNT: usize = 720;
+/// Bonding curve: discriminator, five reserves, complete flag, then the creator.
+const CURVE_CREATOR: usize = 49;
+/// Create accounts: mint, mint authority, bonding curve, associated bonding curve,
+/// global, metadata program, metadata, user.
+const CREATE_MINT_INDEX: usize = 0;
+const CREATE_USER_INDEX: usize = 7;
+/// `getTransaction` calls in flight at once when reading a creator's history
+const CREATOR_FETCH_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Clone)]
+pub enum Verdict {
+    Safe,
+    Blocked(String),
+}
+
+/// Rug and honeypot checks run before copying a buy of a mint. Verdicts are cached
+/// per mint and creator launches per creator, for `cache_secs`.
+pub struct SafetyGate {
+    config: SafetyConfig,
+    rpc: RpcClient,
+    resolver: Arc<PoolResolver>,
+    verdicts: Mutex<HashMap<String, (Instant, Verdict)>>,
+    /// Mints launched by each creator checked
+    launches: Mutex<HashMap<Pubkey, (Instant, Vec<Pubkey>)>>,
+}
+
+impl SafetyGate {
+    pub fn new(config: &Config, resolver: Arc<PoolResolver>) -> Self {
+        Self {
+            config: config.safety.clone(),
+            rpc: RpcClient::new(&config.rpc.endpoint),
+            resolver,
+            verdicts: Mutex::new(HashMap::new()),
+            launches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn cached(&self, mint: &str) -> Option<Verdict> {
+        let verdicts = match self.verdicts.lock() {
+            Ok(verdicts) => verdicts,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        verdicts.get(mint)
+            .filter(|(checked_at, _)| checked_at.elapsed() < Duration::from_secs(self.config.cache_secs))
+            .map(|(_, verdict)| verdict.clone())
+    }
+
+    /// Checks the mint of a target buy. Checks that could not run block the copy too,
+    /// but are not cached.
+    pub async fn check(&self, trade: &TradeEvent) -> Verdict {
+        if let Some(verdict) = self.cached(&trade.mint) {
+            return verdict;
+        }
+        let verdict = match self.inspect(trade).await {
+            Ok(None) => Verdict::Safe,
+            Ok(Some(reason)) => Verdict::Blocked(reason),
+            Err(e) => return Verdict::Blocked(format!("safety checks failed: {:#}", e)),
+        };
+        match self.verdicts.lock() {
+            Ok(mut verdicts) => verdicts.insert(trade.mint.clone(), (Instant::now(), verdict.clone())),
+            Err(poisoned) => poisoned.into_inner().insert(trade.mint.clone(), (Instant::now(), verdict.clone())),
+        };
+        verdict
+    }
+
+    /// The reason to block the mint, if any.
+    async fn inspect(&self, trade: &TradeEvent) -> Result<Option<String>> {
+        let mint = Pubkey::from_str(&trade.mint)?;
+        let pool = Pubkey::from_str(&trade.pool)?;
+        let account = self.rpc.get_account(&mint).await?
+            .ok_or_else(|| anyhow!("Mint {} not found", mint))?;
+        let info = MintInfo::parse(account.owner, &account.data)?;
+        if let (false, Some(authority)) = (self.config.allow_mint_authority, info.mint_authority) {
+            return Ok(Some(format!("mint authority {} is active", authority)));
+        }
+        if let (false, Some(authority)) = (self.config.allow_freeze_authority, info.freeze_authority) {
+            return Ok(Some(format!("freeze authority {} is active", authority)));
+        }
+        if let Some(reason) = self.check_holders(trade.dex, &pool, &mint, &info).await? {
+            return Ok(Some(reason));
+        }
+        match trade.dex {
+            Dex::Raydium => self.check_lp_burned(&pool).await,
+            Dex::Pumpfun => self.check_creator(&pool, &mint).await,
+        }
+    }
+
+    /// Share of the supply held by the largest holders, leaving out the pool's own
+    /// token account.
+    async fn check_holders(&self, dex: Dex, pool: &Pubkey, mint: &Pubkey, info: &MintInfo) -> Result<Option<String>> {
+        if self.config.top_holders == 0 || info.supply == 0 {
+            return Ok(None);
+        }
+        let pool_accounts = match dex {
+            Dex::Raydium => {
+                let accounts = self.resolver.resolve(pool).await?;
+                vec![accounts.amm_coin_vault, accounts.amm_pc_vault]
+            }
+            Dex::Pumpfun => vec![associated_token_address(pool, mint, &info.token_program)],
+        };
+        let held: u64 = self.rpc.get_token_largest_accounts(mint).await?.into_iter()
+            .filter(|(account, _)| !pool_accounts.contains(account))
+            .take(self.config.top_holders)
+            .map(|(_, amount)| amount)
+            .sum();
+        let share = held as f64 / info.supply as f64;
+        if share > self.config.max_top_holders_share {
+            return Ok(Some(format!("top {} holders own {:.1}% of the supply", self.config.top_holders, share * 100.0)));
+        }
+        Ok(None)
+    }
+
+    async fn check_lp_burned(&self, pool: &Pubkey) -> Result<Option<String>> {
+        if self.config.min_lp_burned <= 0.0 {
+            return Ok(None);
+        }
+        let amm = self.rpc.get_account(pool).await?
+            .ok_or_else(|| anyhow!("Pool {} not found", pool))?;
+        let lp_mint = amm.data.get(AMM_LP_MINT..AMM_LP_MINT + 32)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Pubkey::new_from_array)
+            .ok_or_else(|| anyhow!("{} is not an AMM v4 pool", pool))?;
+        let lp_amount = amm.data.get(AMM_LP_AMOUNT..AMM_LP_AMOUNT + 8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u64::from_le_bytes)
+            .ok_or_else(|| anyhow!("{} is not an AMM v4 pool", pool))?;
+        let lp = self.rpc.get_account(&lp_mint).await?
+            .ok_or_else(|| anyhow!("LP mint {} not found", lp_mint))?;
+        let lp_supply = MintInfo::parse(lp.owner, &lp.data)?.supply;
+        let burned = match lp_amount {
+            0 => 0.0,
+            _ => 1.0 - lp_supply.min(lp_amount) as f64 / lp_amount as f64,
+        };
+        if burned < self.config.min_lp_burned {
+            return Ok(Some(format!("only {:.1}% of the LP supply is burned", burned * 100.0)));
+        }
+        Ok(None)
+    }
+
+    /// Counts the other mints the bonding curve's creator launched within its recent
+    /// transactions.
+    async fn check_creator(&self, bonding_curve: &Pubkey, mint: &Pubkey) -> Result<Option<String>> {
+        if self.config.creator_history == 0 {
+            return Ok(None);
+        }
+        let curve = self.rpc.get_account(bonding_curve).await?
+            .ok_or_else(|| anyhow!("Bonding curve {} not found", bonding_curve))?;
+        let Some(creator) = curve.data.get(CURVE_CREATOR..CURVE_CREATOR + 32)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Pubkey::new_from_array)
+        else {
+            // Curves created before the creator field was added
+            return Ok(None);
+        };
+
+        let launched = self.launches(&creator).await?;
+        let launches = launched.iter().filter(|launched| *launched != mint).count();
+        if launches > self.config.max_creator_launches {
+            return Ok(Some(format!("creator {} launched {} other mints recently", creator, launches)));
+        }
+        Ok(None)
+    }
+
+    /// Mints `creator` launched within its recent transactions, cached per creator for
+    /// `cache_secs`. The transactions are fetched a few at a time, and those that can't
+    /// be fetched or decoded are skipped.
+    async fn launches(&self, creator: &Pubkey) -> Result<Vec<Pubkey>> {
+        if let Some(launched) = self.cached_launches(creator) {
+            return Ok(launched);
+        }
+        let signatures: Vec<String> = self.rpc.get_signatures_for_address(&creator.to_string(), None, self.config.creator_history).await?
+            .into_iter()
+            .filter(|info| info.err.is_none())
+            .map(|info| info.signature)
+            .collect();
+        let rpc = &self.rpc;
+        let transactions: Vec<_> = stream::iter(signatures)
+            .map(move |signature| async move {
+                let result = rpc.get_transaction(&signature).await;
+                (signature, result)
+            })
+            .buffer_unordered(CREATOR_FETCH_CONCURRENCY)
+            .collect()
+            .await;
+        let mut launched = vec![];
+        for (signature, result) in transactions {
+            let ixs = match result.and_then(|tx| tx.map(|(_, tx)| flatten_transaction_response(&tx)).transpose()) {
+                Ok(Some(ixs)) => ixs,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Skipping creator transaction {}: {:#}", signature, e);
+                    continue;
+                }
+            };
+            launched.extend(ixs.iter()
+                .filter(|ix| ix.instruction.program_id == PUMPFUN_PROGRAM_ID)
+                .filter_map(|ix| match PumpfunParser::parse_instruction(&ix.instruction) {
+                    Ok(PumpfunInstruction::Create(_, accounts))
+                        if accounts.get(CREATE_USER_INDEX).map(|meta| meta.pubkey) == Some(*creator) =>
+                    {
+                        accounts.get(CREATE_MINT_INDEX).map(|meta| meta.pubkey)
+                    }
+                    _ => None,
+                }));
+        }
+        match self.launches.lock() {
+            Ok(mut launches) => launches.insert(*creator, (Instant::now(), launched.clone())),
+            Err(poisoned) => poisoned.into_inner().insert(*creator, (Instant::now(), launched.clone())),
+        };
+        Ok(launched)
+    }
+
+    fn cached_launches(&self, creator: &Pubkey) -> Option<Vec<Pubkey>> {
+        let launches = match self.launches.lock() {
+            Ok(launches) => launches,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        launches.get(creator)
+            .filter(|(checked_at, _)| checked_at.elapsed() < Duration::from_secs(self.config.cache_secs))
+            .map(|(_, launched)| launched.clone())
+    }
+}
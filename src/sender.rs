@@ -0,0 +1,176 @@
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use futures::future::join_all;
+use log::{info, warn};
+use serde_json::json;
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey, signature::Signature, system_instruction, transaction::Transaction};
+use crate::config::{Config, SenderConfig};
+use crate::rpc::RpcClient;
+
+/// Where signed copy transactions are submitted.
+#[async_trait]
+pub trait TxSender: Send + Sync {
+    /// Submits a signed transaction and returns its signature.
+    async fn send(&self, transaction: &Transaction) -> Result<Signature>;
+
+    /// Instructions appended to every transaction sent this way, like a bundle tip.
+    fn extra_instructions(&self, _payer: &Pubkey) -> Vec<Instruction> {
+        vec![]
+    }
+}
+
+pub fn sender_from_config(config: &Config) -> Result<Arc<dyn TxSender>> {
+    Ok(match &config.execution.sender {
+        SenderConfig::Rpc { skip_preflight } => Arc::new(RpcSender::new(&config.rpc.endpoint, *skip_preflight)),
+        SenderConfig::Fanout { urls, skip_preflight } => {
+            if urls.is_empty() {
+                return Err(anyhow!("Fan-out sender needs at least one URL"));
+            }
+            Arc::new(FanOutSender {
+                senders: urls.iter().map(|url| RpcSender::new(url, *skip_preflight)).collect(),
+            })
+        }
+        SenderConfig::Bundle { url, tip_account, tip_lamports } => Arc::new(BundleSender {
+            block_engine: RpcClient::new(url),
+            tip_account: Pubkey::from_str(tip_account)?,
+            tip_lamports: *tip_lamports,
+        }),
+        SenderConfig::Mock => Arc::new(MockSender::default()),
+    })
+}
+
+/// `sendTransaction` to a single RPC node, which is told not to retry it.
+pub struct RpcSender {
+    rpc: RpcClient,
+    skip_preflight: bool,
+}
+
+impl RpcSender {
+    pub fn new(endpoint: &str, skip_preflight: bool) -> Self {
+        Self { rpc: RpcClient::new(endpoint), skip_preflight }
+    }
+}
+
+#[async_trait]
+impl TxSender for RpcSender {
+    async fn send(&self, transaction: &Transaction) -> Result<Signature> {
+        self.rpc.send_transaction(transaction, self.skip_preflight).await
+    }
+}
+
+/// Sends the same transaction to every node at once. Succeeds if any node took it.
+pub struct FanOutSender {
+    senders: Vec<RpcSender>,
+}
+
+#[async_trait]
+impl TxSender for FanOutSender {
+    async fn send(&self, transaction: &Transaction) -> Result<Signature> {
+        let results = join_all(self.senders.iter().map(|sender| sender.send(transaction))).await;
+        let mut signature = None;
+        let mut last_error = None;
+        for (sender, result) in self.senders.iter().zip(results) {
+            match result {
+                Ok(sent) => signature = Some(sent),
+                Err(e) => {
+                    warn!("Failed to send through {}: {:#}", sender.rpc.endpoint(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        signature.ok_or_else(|| last_error.unwrap_or_else(|| anyhow!("No fan-out sender")))
+    }
+}
+
+/// `sendBundle` to a block engine, as a one-transaction bundle carrying a tip to
+/// `tip_account`.
+pub struct BundleSender {
+    block_engine: RpcClient,
+    tip_account: Pubkey,
+    tip_lamports: u64,
+}
+
+#[async_trait]
+impl TxSender for BundleSender {
+    async fn send(&self, transaction: &Transaction) -> Result<Signature> {
+        let signature = *transaction.signatures.first().ok_or_else(|| anyhow!("Unsigned transaction"))?;
+        let encoded = BASE64_STANDARD.encode(bincode::serialize(transaction)?);
+        let bundle_id: String = self.block_engine.call("sendBundle", json!([[encoded], { "encoding": "base64" }])).await?
+            .ok_or_else(|| anyhow!("Empty sendBundle response"))?;
+        info!("Bundle {} carries {}", bundle_id, signature);
+        Ok(signature)
+    }
+
+    fn extra_instructions(&self, payer: &Pubkey) -> Vec<Instruction> {
+        vec![system_instruction::transfer(payer, &self.tip_account, self.tip_lamports)]
+    }
+}
+
+/// Records transactions instead of sending them, to run the execution pipeline
+/// offline. The executor then tracks them on a `MockRpc` chain, where they land
+/// right away and are booked at their quote.
+#[derive(Default)]
+pub struct MockSender {
+    sent: Mutex<Vec<Transaction>>,
+}
+
+impl MockSender {
+    /// The transactions recorded so far, in the order they were sent.
+    pub fn sent(&self) -> MutexGuard<'_, Vec<Transaction>> {
+        match self.sent.lock() {
+            Ok(sent) => sent,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+#[async_trait]
+impl TxSender for MockSender {
+    async fn send(&self, transaction: &Transaction) -> Result<Signature> {
+        let signature = *transaction.signatures.first().ok_or_else(|| anyhow!("Unsigned transaction"))?;
+        let mut sent = self.sent();
+        sent.push(transaction.clone());
+        info!("MOCK recorded {} ({} so far)", signature, sent.len());
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::{hash::Hash, signature::{Keypair, Signer}};
+
+    fn transfer(payer: &Keypair, lamports: u64) -> Transaction {
+        let instruction = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), lamports);
+        Transaction::new_signed_with_payer(&[instruction], Some(&payer.pubkey()), &[payer], Hash::new_unique())
+    }
+
+    #[tokio::test]
+    async fn mock_sender_records_what_it_is_sent() {
+        let payer = Keypair::new();
+        let sender = MockSender::default();
+        let transactions = [transfer(&payer, 1), transfer(&payer, 2)];
+        for transaction in &transactions {
+            assert_eq!(sender.send(transaction).await.unwrap(), transaction.signatures[0]);
+        }
+        assert_eq!(*sender.sent(), transactions);
+        assert!(sender.extra_instructions(&payer.pubkey()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn mock_sender_refuses_unsigned_transactions() {
+        let sender = MockSender::default();
+        assert!(sender.send(&Transaction::default()).await.is_err());
+        assert!(sender.sent().is_empty());
+    }
+
+    #[test]
+    fn bundle_sender_tips_the_tip_account() {
+        let (payer, tip_account) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let sender = BundleSender { block_engine: RpcClient::new("http://localhost:0"), tip_account, tip_lamports: 10_000 };
+        assert_eq!(sender.extra_instructions(&payer), vec![system_instruction::transfer(&payer, &tip_account, 10_000)]);
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use yellowstone_grpc_proto::prelude::{SlotStatus, SubscribeUpdateSlot};
+use crate::config::{CommitmentConfig, ConfirmLevel};
+use crate::trade::TradeEvent;
+
+/// Number of slots of status history kept for trades that arrive after their slot
+/// was already resolved.
+const STATUS_HISTORY_SLOTS: u64 = 1_000;
+
+#[derive(Debug)]
+pub enum Resolution {
+    Confirmed,
+    /// The target's trade never made it on chain, with the reason
+    Orphaned(String),
+}
+
+/// Follows slot status updates to tell which target trades, acted on at processed
+/// commitment, were later confirmed and which landed on a dead or skipped fork.
+pub struct SlotTracker {
+    confirm_at: ConfirmLevel,
+    max_unconfirmed_slots: u64,
+    pending: BTreeMap<u64, Vec<TradeEvent>>,
+    statuses: BTreeMap<u64, SlotStatus>,
+    tip: u64,
+}
+
+impl SlotTracker {
+    pub fn new(config: &CommitmentConfig) -> Self {
+        Self {
+            confirm_at: config.confirm_at,
+            max_unconfirmed_slots: config.max_unconfirmed_slots,
+            pending: BTreeMap::new(),
+            statuses: BTreeMap::new(),
+            tip: 0,
+        }
+    }
+
+    /// Starts tracking a trade. If its slot has already been resolved the resolution
+    /// is returned right away.
+    pub fn track(&mut self, trade: TradeEvent) -> Option<(TradeEvent, Resolution)> {
+        match self.statuses.get(&trade.slot) {
+            Some(SlotStatus::SlotDead) => Some((trade, Resolution::Orphaned("slot is dead".to_owned()))),
+            Some(status) if self.is_confirmed(*status) => Some((trade, Resolution::Confirmed)),
+            _ => {
+                self.pending.entry(trade.slot).or_default().push(trade);
+                None
+            }
+        }
+    }
+
+    pub fn on_slot(&mut self, update: &SubscribeUpdateSlot) -> Vec<(TradeEvent, Resolution)> {
+        let status = update.status();
+        let mut resolved = vec![];
+
+        let previous = self.statuses.insert(update.slot, status);
+        // Status updates of a slot can arrive out of order, never downgrade it
+        if let Some(previous) = previous.filter(|previous| rank(*previous) > rank(status)) {
+            self.statuses.insert(update.slot, previous);
+            return resolved;
+        }
+        self.tip = self.tip.max(update.slot);
+
+        match status {
+            SlotStatus::SlotDead => {
+                let reason = format!("slot is dead: {}", update.dead_error.as_deref().unwrap_or("unknown error"));
+                resolved.extend(self.resolve_slot(update.slot, || Resolution::Orphaned(reason.clone())));
+            }
+            status if self.is_confirmed(status) => {
+                resolved.extend(self.resolve_slot(update.slot, || Resolution::Confirmed));
+            }
+            _ => {}
+        }
+
+        // Once a slot is rooted, earlier slots that never reached it were skipped
+        if status == SlotStatus::SlotFinalized {
+            let skipped: Vec<u64> = self.pending.range(..update.slot).map(|(slot, _)| *slot).collect();
+            for slot in skipped {
+                resolved.extend(self.resolve_slot(slot, || Resolution::Orphaned("slot was skipped".to_owned())));
+            }
+        }
+
+        // Trades whose slot never got a status are given up on eventually
+        let expired: Vec<u64> = self.pending
+            .range(..self.tip.saturating_sub(self.max_unconfirmed_slots))
+            .map(|(slot, _)| *slot)
+            .collect();
+        for slot in expired {
+            resolved.extend(self.resolve_slot(slot, || Resolution::Orphaned("slot was never confirmed".to_owned())));
+        }
+
+        let keep_from = self.tip.saturating_sub(STATUS_HISTORY_SLOTS);
+        self.statuses = self.statuses.split_off(&keep_from);
+
+        resolved
+    }
+
+    fn resolve_slot(&mut self, slot: u64, resolution: impl Fn() -> Resolution) -> Vec<(TradeEvent, Resolution)> {
+        self.pending
+            .remove(&slot)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|trade| (trade, resolution()))
+            .collect()
+    }
+
+    fn is_confirmed(&self, status: SlotStatus) -> bool {
+        match self.confirm_at {
+            ConfirmLevel::Confirmed => matches!(status, SlotStatus::SlotConfirmed | SlotStatus::SlotFinalized),
+            ConfirmLevel::Finalized => status == SlotStatus::SlotFinalized,
+        }
+    }
+}
+
+fn rank(status: SlotStatus) -> u8 {
+    match status {
+        SlotStatus::SlotFirstShredReceived => 0,
+        SlotStatus::SlotCreatedBank => 1,
+        SlotStatus::SlotCompleted => 2,
+        SlotStatus::SlotProcessed => 3,
+        SlotStatus::SlotConfirmed => 4,
+        SlotStatus::SlotFinalized => 5,
+        SlotStatus::SlotDead => 6,
+    }
+}
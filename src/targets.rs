@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use log::{info, warn};
+use tokio::time::{interval, Duration};
+use crate::backfill::start_backfill;
+use crate::config::{Config, TargetProfile};
+use crate::mints::MintResolver;
+use crate::positions::PositionBook;
+
+/// Process-wide target profiles, set from the config on startup and replaced when the
+/// config file is reloaded.
+pub struct TargetRegistry {
+    targets: RwLock<Vec<TargetProfile>>,
+    /// Bumped whenever the set of active wallets changes, so subscriptions know to refresh
+    version: AtomicU64,
+}
+
+pub static TARGETS: TargetRegistry = TargetRegistry {
+    targets: RwLock::new(Vec::new()),
+    version: AtomicU64::new(0),
+};
+
+impl TargetRegistry {
+    fn read(&self) -> RwLockReadGuard<'_, Vec<TargetProfile>> {
+        match self.targets.read() {
+            Ok(targets) => targets,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Replaces the profiles. Returns the wallets that became active.
+    pub fn set(&self, targets: Vec<TargetProfile>) -> Vec<String> {
+        let before: HashSet<String> = self.active_wallets().into_iter().collect();
+        let after: Vec<String> = active(&targets).collect();
+        match self.targets.write() {
+            Ok(mut current) => *current = targets,
+            Err(poisoned) => *poisoned.into_inner() = targets,
+        }
+        let added: Vec<String> = after.iter().filter(|wallet| !before.contains(*wallet)).cloned().collect();
+        if !added.is_empty() || after.len() != before.len() {
+            self.version.fetch_add(1, Ordering::Relaxed);
+        }
+        added
+    }
+
+    /// The profile of `wallet`, enabled or not.
+    pub fn profile(&self, wallet: &str) -> Option<TargetProfile> {
+        self.read().iter().find(|target| target.wallet == wallet).cloned()
+    }
+
+    /// Enabled targets with a wallet set, the ones that are subscribed to and copied.
+    pub fn active_wallets(&self) -> Vec<String> {
+        active(&self.read()).collect()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+}
+
+fn active(targets: &[TargetProfile]) -> impl Iterator<Item = String> + '_ {
+    targets.iter().filter(|target| target.enabled && !target.wallet.is_empty()).map(|target| target.wallet.clone())
+}
+
+/// Re-reads `wallets.targets` from the config file every `wallets.reload_secs`, and
+/// backfills the targets added since.
+pub fn start_target_reload(config: Config, positions: Arc<Mutex<PositionBook>>, mints: Arc<MintResolver>) {
+    if config.wallets.reload_secs == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(config.wallets.reload_secs));
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let targets = match Config::load() {
+                Ok(reloaded) => reloaded.wallets.targets,
+                Err(e) => {
+                    warn!("Failed to reload the targets: {:#}", e);
+                    continue;
+                }
+            };
+            let added = TARGETS.set(targets);
+            if added.is_empty() {
+                continue;
+            }
+            info!("Targets added: {}", added.join(", "));
+            start_backfill(&config, added, positions.clone(), mints.clone());
+        }
+    });
+}
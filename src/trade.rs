@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Dex {
+    Raydium,
+    Pumpfun,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// A target wallet's SOL <-> token trade, normalized across DEXes.
+///
+/// Reserves are the pool state right after the trade; for Pump.fun they are the
+/// virtual reserves of the bonding curve, which are the ones that set the price.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeEvent {
+    pub dex: Dex,
+    pub signature: String,
+    pub slot: u64,
+    /// Owner of the swap, which is not necessarily the fee payer
+    pub wallet: String,
+    /// Raydium AMM id or Pump.fun bonding curve
+    pub pool: String,
+    pub mint: String,
+    pub side: Side,
+    pub sol_amount: u64,
+    pub token_amount: u64,
+    pub sol_reserve: u64,
+    pub token_reserve: u64,
+    pub price_impact: f64,
+}
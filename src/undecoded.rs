@@ -0,0 +1,63 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use log::warn;
+use serde::Serialize;
+use crate::metrics::{Metrics, METRICS};
+
+/// A transaction that could not be decoded, with the full error chain.
+#[derive(Debug, Serialize)]
+pub struct UndecodedRecord<'a> {
+    pub timestamp_ms: u128,
+    pub dex: &'a str,
+    pub slot: u64,
+    pub signature: &'a str,
+    pub error: String,
+    pub causes: Vec<String>,
+}
+
+/// Append-only JSON lines file collecting transactions that failed to decode, so a
+/// single bad transaction is recorded instead of tearing down the gRPC stream.
+pub struct UndecodedSink {
+    file: Mutex<File>,
+}
+
+impl UndecodedSink {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+
+    pub fn record(&self, dex: &str, slot: u64, signature: &str, error: &anyhow::Error) {
+        Metrics::incr(&METRICS.transactions_undecoded);
+        warn!("{} failed to decode {} at slot {}: {:#}", dex, signature, slot, error);
+
+        let record = UndecodedRecord {
+            timestamp_ms: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or_default(),
+            dex,
+            slot,
+            signature,
+            error: error.to_string(),
+            causes: error.chain().skip(1).map(|cause| cause.to_string()).collect(),
+        };
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to serialize undecoded record: {}", e);
+                return;
+            }
+        };
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Err(e) = writeln!(file, "{}", line) {
+            warn!("Failed to write undecoded record: {}", e);
+        }
+    }
+}